@@ -0,0 +1,22 @@
+#![no_main]
+
+// 任意の入力を読み込み、コンパイルし、実行する。エラーになるのは構わないが、
+// どの段階でもpanicしてはいけない。`cargo +nightly fuzz run eval`で実行する。
+use libfuzzer_sys::fuzz_target;
+use secd::{Interpreter, Limits};
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(input) = std::str::from_utf8(data) else {
+        return;
+    };
+    // 前の入力で定義されたものが影響しないよう、毎回新しいインタプリタを作る。
+    let mut interpreter = Interpreter::new().expect("prelude must load");
+    // 止まらない入力や、メモリを使い切る入力はエラーで打ち切る。
+    interpreter.set_limits(Limits {
+        steps: Some(1_000_000),
+        dump_depth: Some(10_000),
+        heap: Some(1_000_000),
+        ..Limits::default()
+    });
+    let _ = interpreter.eval_str(input);
+});
@@ -12,8 +12,6 @@
 (define cddar (lambda (x) (cdr (cdar x))))
 (define cdddr (lambda (x) (cdr (cddr x))))
 
-(define (zero? x) (eq? x 0))
-
 (define (map fn ls)
   (if (null? ls)
       '()
@@ -27,10 +25,10 @@
 ; quasiquoteまわりについては大部分下記を参考にさせていただいた
 ; http://www.geocities.jp/m_hiroi/func/abcscm36.html#appendix1
 (define unquote
-  (lambda (x) (error)))
+  (lambda (x) (error "unquote appears outside quasiquote" x)))
 
 (define unquote-splicing
-  (lambda (x) (error)))
+  (lambda (x) (error "unquote-splicing appears outside quasiquote" x)))
 
 (define translator-sub
   (lambda (sym ls n succ)
@@ -84,7 +82,7 @@
                 (translator-sub 'unquote (cadr ls) n -1)))
         (if (eq? (car ls) 'unquote-splicing)
             (if (zero? n)
-                (error)
+                (error "unquote-splicing appears outside list" (cadr ls))
                 (if (= n 1)
                     (if (eq? (car (cadr ls)) 'unquote-splicing)
                         (list 'cons (list 'quote 'unquote-splicing) (cadr (cadr ls)))
@@ -93,7 +91,7 @@
             (if (eq? (car ls) 'quasiquote)
                 (translator-sub 'quasiquote (cadr ls) n 1)
                 (list 'cons
-                      (list 'quote (car ls))
+                      (translator (car ls) n)
                       (translator (cdr ls) n)))))))
 
 (define translator
@@ -102,39 +100,94 @@
         (if (pair? (car ls))
             (translator-list ls n)
             (translator-atom ls n))
-        (list 'quote ls))))
+        (if (vector? ls)
+            (list 'list->vector (translator (vector->list ls) n))
+            (list 'quote ls)))))
 
 (define-macro (quasiquote x) (translator x 0))
 
-(define-macro (let args . body)
-  `((lambda ,(map car args) ,@body) ,@(map cadr args)))
-
-(define-macro (let* args . body)
-  (if (null? (cdr args))
-      `(let (,(car args)) ,@body)
-      `(let (,(car args)) (let* ,(cdr args) ,@body))))
-
-(define-macro (and . args)
-  (if (null? args)
-      #t
-      (if (null? (cdr args))
-          (car args)
-          `(if ,(car args) (and ,@(cdr args)) #f))))
-
-(define-macro (or . args)
-  (if (null? args)
-      #f
-      (if (null? (cdr args))
-          (car args)
-          `(if ,(car args) ,(car args) (or ,@(cdr args))))))
-
-(define-macro (cond . args)
-  (if (null? args)
-      '(undefined)
-      (if (eq? (caar args) 'else)
-          `(begin ,@(cdar args))
-          (if (null? (cdar args))
-              `(if ,(caar args) ,(caar args) (cond ,@(cdr args)))
-              `(if ,(caar args)
-                   (begin ,@(cdar args))
-                   (cond ,@(cdr args)))))))
+(define-syntax let
+  (syntax-rules ()
+    ((let ((name val) ...) body1 body2 ...)
+     ((lambda (name ...) body1 body2 ...) val ...))))
+
+(define-syntax let*
+  (syntax-rules ()
+    ((let* () body1 body2 ...)
+     (let () body1 body2 ...))
+    ((let* ((name1 val1) (name2 val2) ...) body1 body2 ...)
+     (let ((name1 val1))
+       (let* ((name2 val2) ...) body1 body2 ...)))))
+
+(define-syntax and
+  (syntax-rules ()
+    ((and) #t)
+    ((and test) test)
+    ((and test1 test2 ...)
+     (if test1 (and test2 ...) #f))))
+
+(define-syntax or
+  (syntax-rules ()
+    ((or) #f)
+    ((or test) test)
+    ((or test1 test2 ...)
+     (let ((x test1))
+       (if x x (or test2 ...))))))
+
+; elseが最後の節でなくても、それ以降の節は無視する
+(define-syntax cond
+  (syntax-rules (else =>)
+    ((cond) (undefined))
+    ((cond (else result1 result2 ...) clause ...)
+     (begin result1 result2 ...))
+    ((cond (test => result) clause ...)
+     (let ((temp test))
+       (if temp (result temp) (cond clause ...))))
+    ((cond (test) clause ...)
+     (or test (cond clause ...)))
+    ((cond (test result1 result2 ...) clause ...)
+     (if test
+         (begin result1 result2 ...)
+         (cond clause ...)))))
+
+; R7RSのguardの参照実装を、多値を使わない形に簡略化したもの
+; 利用者がletやcondを定義し直しても動くよう、展開結果ではlambdaとifしか使わない
+(define-syntax guard
+  (syntax-rules ()
+    ((guard (var clause ...) e1 e2 ...)
+     ((call/cc
+       (lambda (guard-k)
+         (with-exception-handler
+          (lambda (condition)
+            ((call/cc
+              (lambda (handler-k)
+                (guard-k
+                 (lambda ()
+                   ((lambda (var)
+                      (guard-aux
+                       (handler-k
+                        (lambda () (raise-continuable condition)))
+                       clause ...))
+                    condition)))))))
+          (lambda ()
+            ((lambda (result) (guard-k (lambda () result)))
+             ((lambda () e1 e2 ...)))))))))))
+
+(define-syntax guard-aux
+  (syntax-rules (else =>)
+    ((guard-aux reraise (else result1 result2 ...))
+     ((lambda () result1 result2 ...)))
+    ((guard-aux reraise (test => result) clause ...)
+     ((lambda (temp)
+        (if temp (result temp) (guard-aux reraise clause ...)))
+      test))
+    ((guard-aux reraise (test) clause ...)
+     ((lambda (temp)
+        (if temp temp (guard-aux reraise clause ...)))
+      test))
+    ((guard-aux reraise (test result1 result2 ...) clause ...)
+     (if test
+         ((lambda () result1 result2 ...))
+         (guard-aux reraise clause ...)))
+    ((guard-aux reraise)
+     reraise)))
@@ -1,33 +1,90 @@
-use value::{Value, vec2cons};
-use vm::{Machine, SharedCode, MutableCode, Global, CodeOp, Location, Position};
+use error::SchemeError;
+use native::Arity;
+use span::Span;
+use value::{Value, Vector, vec2cons, vec2constant};
+use vm::{Machine, SharedCode, MutableCode, Global, CodeOp, Lambda, Location};
+use syntax::{Alias, SyntaxRules};
+use number::Number;
+use std::borrow::Cow;
 use std::rc::Rc;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Ast {
     Nil,
     Boolean(bool),
-    Integer(i32),
+    Number(Number),
     Symbol(String),
-    List(Vec<Ast>, Box<Ast>),
+    String(String),
+    Char(char),
+    // syntax-rulesの展開によって導入された識別子
+    Alias(Rc<Alias>),
+    // リストの要素と末尾、および読み込んだ位置。展開などで作られたリストは位置を持たない。
+    List(Vec<Ast>, Box<Ast>, Option<Span>),
+    Vector(Vec<Ast>),
+    Bytevector(Vec<u8>),
     Undefined,
 }
 
-type Env = Vec<Ast>;
+// コンパイル時の環境。Varsはlambdaの仮引数で、実行時のフレームに対応する。
+// Macrosはlet-syntaxなどによる局所マクロで、実行時のフレームは持たない。
+#[derive(Debug)]
+enum Scope {
+    Vars(Ast),
+    Macros(Vec<(Ast, Rc<SyntaxRules>)>),
+}
+
+type Env = Vec<Scope>;
+
+// コンパイルするリストの入れ子の深さの上限。読み込み時の深さはreaderで制限しているが、
+// マクロの展開や準引用ではより深い式が作られうるので、コンパイラのスタックが溢れる前に止める。
+const MAX_DEPTH: usize = 1000;
+
+enum Binding {
+    Local(Location),
+    Syntax(Rc<SyntaxRules>),
+    Global(String),
+}
 
 impl Ast {
     pub fn to_value(&self) -> Value {
+        self.to_datum(true)
+    }
+
+    // 引用された定数として値にする。ペアは書き換えできないものとして作る。
+    pub fn to_constant(&self) -> Value {
+        self.to_datum(false)
+    }
+
+    fn to_datum(&self, mutable: bool) -> Value {
         match *self {
             Ast::Nil => Value::Nil,
             Ast::Boolean(b) => Value::Boolean(b),
-            Ast::Integer(i) => Value::Integer(i),
+            Ast::Number(ref n) => Value::Number(n.to_owned()),
             Ast::Symbol(ref s) => Value::Symbol(s.to_owned()),
-            Ast::List(ref former, ref last) => {
-                vec2cons(&former
-                              .iter()
-                              .map(|x| x.to_value())
-                              .collect::<Vec<Value>>(),
-                         last.to_value())
+            Ast::String(ref s) => Value::string(s),
+            Ast::Char(c) => Value::Char(c),
+            Ast::Alias(ref alias) => alias.name.to_datum(mutable),
+            Ast::List(ref former, ref last, _) => {
+                let former = former
+                    .iter()
+                    .map(|x| x.to_datum(mutable))
+                    .collect::<Vec<Value>>();
+                if mutable {
+                    vec2cons(&former, last.to_datum(mutable))
+                } else {
+                    vec2constant(&former, last.to_datum(mutable))
+                }
             }
+            Ast::Vector(ref elems) => {
+                let values = elems.iter().map(|x| x.to_datum(mutable)).collect();
+                if mutable {
+                    Value::vector(values)
+                } else {
+                    Value::Vector(Rc::new(Vector::new(values, false)))
+                }
+            }
+            Ast::Bytevector(ref bytes) if mutable => Value::bytevector(bytes.to_owned()),
+            Ast::Bytevector(ref bytes) => Value::Bytevector(Rc::new(Vector::new(bytes.to_owned(), false))),
             Ast::Undefined => Value::Undefined,
         }
     }
@@ -37,126 +94,413 @@ impl Ast {
     }
 
     pub fn new_list(former: &[Ast], last: Ast) -> Ast {
-        Ast::List(former.to_owned(), Box::new(last))
+        Ast::List(former.to_owned(), Box::new(last), None)
+    }
+
+    // リストであれば位置をspanにする。
+    pub fn with_span(self, span: Option<Span>) -> Ast {
+        match self {
+            Ast::List(former, last, _) => Ast::List(former, last, span),
+            ast => ast,
+        }
+    }
+
+    // シンボルであればその名前を、リネームされた識別子であれば元の名前を返す。
+    pub fn identifier_name(&self) -> Option<&str> {
+        match *self {
+            Ast::Symbol(ref name) => Some(name),
+            Ast::Alias(ref alias) => alias.name.identifier_name(),
+            _ => None,
+        }
     }
 
-    pub fn compile(&self, global: &Global) -> Result<SharedCode, String> {
+    // リストを要素と末尾に分解する。(a . (b c))のような入れ子も平らにする。
+    pub fn flatten(&self) -> (Vec<Ast>, Ast) {
+        let mut elems = Vec::new();
+        let mut ast = self;
+        loop {
+            match *ast {
+                Ast::List(ref former, ref last, _) => {
+                    elems.extend_from_slice(former);
+                    ast = last;
+                }
+                ref last => return (elems, last.to_owned()),
+            }
+        }
+    }
+
+    pub fn compile(&self, global: &mut Global) -> Result<SharedCode, SchemeError> {
         let mut env = Vec::new();
-        let mut code = Vec::new();
-        self.compile_helper(&mut env, &mut code, global)?;
-        Ok(Rc::new(code.into_boxed_slice()))
+        let mut code = MutableCode::new();
+        self.compile_helper(&mut env, &mut code, global, false)?;
+        Ok(code.into_shared())
     }
 
+    // tailはこの式が末尾位置にあるかどうか。末尾位置の関数適用やifは
+    // TApp、TSelにコンパイルされ、VMはDumpを積まずに現在のフレームを再利用する。
     fn compile_helper(&self,
                       env: &mut Env,
                       code: &mut MutableCode,
-                      global: &Global)
-                      -> Result<(), String> {
+                      global: &mut Global,
+                      tail: bool)
+                      -> Result<(), SchemeError> {
         match *self {
-            Ast::Symbol(ref name) => {
-                if let Some(location) = location(self, env) {
-                    code.push(CodeOp::Ld(location));
-                } else {
-                    code.push(CodeOp::Ldg(name.to_owned()));
+            Ast::Symbol(_) | Ast::Alias(_) => {
+                match resolve(self, env) {
+                    Binding::Local(location) => code.push(CodeOp::Ld(location)),
+                    Binding::Global(name) => code.push(CodeOp::Ldg(global.slot(&name))),
+                    Binding::Syntax(_) => {
+                        return Err(SchemeError::syntax(&format!("invalid use of syntax: {}",
+                                                                self.identifier_name().unwrap_or(""))))
+                    }
                 }
                 Ok(())
             }
-            Ast::List(ref form, ref last) => {
-                if **last != Ast::Nil {
-                    return Err("proper list required".to_owned());
+            Ast::List(ref form, ref last, ref span) => {
+                if code.depth() >= MAX_DEPTH {
+                    return Err(SchemeError::syntax("expression nested too deeply").at(span.as_ref()));
                 }
-                if let Some(&Ast::Symbol(ref name)) = form.get(0) {
-                    if let Some(&Value::Macro(ref macro_code, _)) = global.get(name) {
-
-                        // 最後のRtnを削除
-                        let mut owned_macro_code = Vec::with_capacity(macro_code.len());
-                        owned_macro_code.extend_from_slice(&macro_code[1..]);
-
-                        let macro_args = form[1..].iter().map(|ast| ast.to_value()).collect();
-                        let result =
-                            Machine::run(vec![macro_args],
-                                         Rc::new(owned_macro_code.into_boxed_slice()),
-                                         &mut global.to_owned());
-                        result
-                            .unwrap()
-                            .to_ast()
-                            .compile_helper(env, code, global)
-                    } else {
-                        match name.as_str() {
-                            "quote" => {
-                                if form.len() != 2 {
-                                    return Err("malformed quote".to_owned());
-                                }
-                                code.push(CodeOp::Ldc(form[1].to_owned()));
-                                Ok(())
-                            }
-                            "define" => {
-                                if form.len() < 3 {
-                                    return Err("malformed define".to_owned());
-                                }
-                                define(&form[1], &form[2..], env, code, global)
-                            }
-                            "define-macro" => {
-                                if form.len() < 3 {
-                                    return Err("malformed define-macro".to_owned());
-                                }
-                                define_macro(&form[1], &form[2..], env, code, global)
-                            }
-                            "lambda" => {
-                                let (params, body) = form[1..].split_at(1);
-                                lambda(params[0].to_owned(), body, env, code, global)
-                            }
-                            "if" => {
-                                let n = form.len();
-                                if n < 3 || 4 < n {
-                                    return Err("malformed if".to_owned());
-                                }
-                                let alt = form.get(3);
-                                if_(&form[1], &form[2], alt, env, code, global)
-                            }
-                            "begin" => {
-                                if form.len() < 2 {
-                                    code.push(CodeOp::Ldc(Ast::Integer(0)));
-                                    Ok(())
-                                } else {
-                                    begin(&form[1..], env, code, global)
-                                }
+                global.spend().map_err(|e| e.at(span.as_ref()))?;
+                // 生成されるCodeOpとエラーに、このリストの位置を記録する。
+                let outer = code.enter(span.as_ref());
+                let result = self.compile_form(form, last, env, code, global, tail);
+                code.leave(outer);
+                result.map_err(|e| e.at(span.as_ref()))
+            }
+            ref ast => {
+                code.push(CodeOp::Ldc(ast.to_constant()));
+                Ok(())
+            }
+        }
+    }
+
+    fn compile_form(&self,
+                    form: &[Ast],
+                    last: &Ast,
+                    env: &mut Env,
+                    code: &mut MutableCode,
+                    global: &mut Global,
+                    tail: bool)
+                    -> Result<(), SchemeError> {
+        if *last != Ast::Nil {
+            return Err(SchemeError::syntax("proper list required"));
+        }
+        if form.is_empty() {
+            return Err(SchemeError::syntax("empty application"));
+        }
+        if let Some(expanded) = self.expand(form, env, global)? {
+            return expanded.compile_helper(env, code, global, tail);
+        }
+        match resolve(&form[0], env) {
+            Binding::Global(ref name) if form[0].identifier_name().is_some() => {
+                special_form(name, form, env, code, global, tail)
+            }
+            _ => apply(form, env, code, global, tail),
+        }
+    }
+
+    // マクロの呼び出しであれば、1段だけ展開した式を返す。
+    // 展開した式は続けてコンパイルされるとは限らないので、展開ごとに資源を数える。
+    fn expand(&self, form: &[Ast], env: &[Scope], global: &mut Global) -> Result<Option<Ast>, SchemeError> {
+        let rules = match form.first() {
+            Some(head) if head.identifier_name().is_some() => {
+                match resolve(head, env) {
+                    Binding::Syntax(rules) => rules,
+                    Binding::Global(name) => {
+                        match global.get(&name).cloned() {
+                            Some(Value::Macro(lambda, macro_env)) => {
+                                global.spend()?;
+                                let closure = Value::Closure(lambda, macro_env);
+                                let macro_args = form[1..].iter().map(|ast| ast.to_value()).collect();
+                                return Machine::apply(closure, macro_args, global)?.to_ast().map(Some);
                             }
-                            _ => apply(form, env, code, global),
+                            Some(Value::Syntax(rules)) => rules,
+                            _ => return Ok(None),
                         }
                     }
-                } else {
-                    apply(form, env, code, global)
+                    Binding::Local(_) => return Ok(None),
                 }
             }
-            ref ast => {
-                code.push(CodeOp::Ldc(ast.to_owned()));
+            _ => return Ok(None),
+        };
+        global.spend()?;
+        let same_binding = |literal: &Ast, input: &Ast| {
+            locate(literal, env, rules.env_len.min(env.len())) == locate(input, env, env.len())
+        };
+        rules.expand(self, &same_binding).map(Some).map_err(|e| SchemeError::syntax(&e))
+    }
+}
+
+fn special_form(name: &str,
+                form: &[Ast],
+                env: &mut Env,
+                code: &mut MutableCode,
+                global: &mut Global,
+                tail: bool)
+                -> Result<(), SchemeError> {
+    match name {
+        "quote" => {
+            if form.len() != 2 {
+                return Err(SchemeError::syntax("malformed quote"));
+            }
+            code.push(CodeOp::Ldc(form[1].to_constant()));
+            Ok(())
+        }
+        "define" => {
+            if form.len() < 3 {
+                return Err(SchemeError::syntax("malformed define"));
+            }
+            // 本体の先頭にある定義はbodyでletrec*に書き換えられるので、ここに来るのはそれ以外。
+            if env.iter().any(|scope| matches!(*scope, Scope::Vars(_))) {
+                return Err(SchemeError::syntax("define is only allowed at the beginning of a body"));
+            }
+            define(&form[1], &form[2..], env, code, global)
+        }
+        "set!" => {
+            if form.len() != 3 {
+                return Err(SchemeError::syntax("malformed set!"));
+            }
+            set(&form[1], &form[2], env, code, global)
+        }
+        "define-macro" => {
+            if form.len() < 3 {
+                return Err(SchemeError::syntax("malformed define-macro"));
+            }
+            define_macro(&form[1], &form[2..], env, code, global)
+        }
+        "define-syntax" => {
+            if form.len() != 3 {
+                return Err(SchemeError::syntax("malformed define-syntax"));
+            }
+            define_syntax(&form[1], &form[2], env, code)
+        }
+        "let-syntax" | "letrec-syntax" => {
+            if form.len() < 3 {
+                return Err(SchemeError::syntax(&format!("malformed {}", name)));
+            }
+            let_syntax(&form[1], &form[2..], env, code, global, tail, name == "letrec-syntax")
+        }
+        "lambda" => {
+            if form.len() < 3 {
+                return Err(SchemeError::syntax("malformed lambda"));
+            }
+            lambda(None, form[1].to_owned(), &form[2..], env, code, global)
+        }
+        "letrec" | "letrec*" => {
+            if form.len() < 3 {
+                return Err(SchemeError::syntax(&format!("malformed {}", name)));
+            }
+            let (bindings, last) = form[1].flatten();
+            if last != Ast::Nil {
+                return Err(SchemeError::syntax(&format!("malformed {}", name)));
+            }
+            let mut defs = Vec::with_capacity(bindings.len());
+            for binding in &bindings {
+                match *binding {
+                    Ast::List(ref spec, ref last, _) if **last == Ast::Nil && spec.len() == 2 &&
+                                                     spec[0].identifier_name().is_some() => {
+                        defs.push((&spec[0], Init::new(&spec[1], env, global)));
+                    }
+                    _ => return Err(SchemeError::syntax(&format!("malformed {}", name))),
+                }
+            }
+            letrec(&defs, &form[2..], env, code, global, tail)
+        }
+        "if" => {
+            let n = form.len();
+            if !(3..=4).contains(&n) {
+                return Err(SchemeError::syntax("malformed if"));
+            }
+            let alt = form.get(3);
+            if_(&form[1], &form[2], alt, env, code, global, tail)
+        }
+        "begin" => {
+            if form.len() < 2 {
+                code.push(CodeOp::Ldc(Value::integer(0)));
                 Ok(())
+            } else {
+                begin(&form[1..], env, code, global, tail)
             }
         }
+        _ => apply(form, env, code, global, tail),
     }
 }
 
-fn begin(body: &[Ast], env: &mut Env, code: &mut MutableCode, global: &Global) -> Result<(), String> {
-    for exp in body.iter().rev() {
-        exp.compile_helper(env, code, global)?;
+fn begin(body: &[Ast],
+         env: &mut Env,
+         code: &mut MutableCode,
+         global: &mut Global,
+         tail: bool)
+         -> Result<(), SchemeError> {
+    // 最後の式だけが末尾位置になる。
+    for (i, exp) in body.iter().rev().enumerate() {
+        exp.compile_helper(env, code, global, tail && i == 0)?;
         code.push(CodeOp::Pop)
     }
     code.pop();
     Ok(())
 }
 
-fn lambda(params: Ast,
+// nameは(define (f ...) ...)のように名前の分かる手続きの場合に与える。
+fn lambda(name: Option<&str>,
+          params: Ast,
+          body: &[Ast],
+          env: &mut Env,
+          code: &mut MutableCode,
+          global: &mut Global)
+          -> Result<(), SchemeError> {
+    let mut body_code = code.nested(CodeOp::Rtn);
+    let rest = rest_index(&params);
+    let (required, _) = params.flatten();
+    let arity = match rest {
+        Some(_) => Arity::AtLeast(required.len()),
+        None => Arity::Exactly(required.len()),
+    };
+    env.push(Scope::Vars(params));
+    let result = compile_body(body, env, &mut body_code, global, true);
+    env.pop();
+    result?;
+    if let Some(index) = rest {
+        body_code.push(CodeOp::Rest(index));
+    }
+    code.push(CodeOp::Ldf(Rc::new(Lambda {
+                                      code: body_code.into_shared(),
+                                      name: name.map(|name| name.to_owned()),
+                                      arity,
+                                  })));
+    Ok(())
+}
+
+// 本体の式の種類。定義を探すために、マクロの呼び出しとbeginは先に展開する。
+enum BodyForm {
+    Definition,
+    Splice(Vec<Ast>),
+    Expanded(Ast),
+    Expression,
+}
+
+fn body_form(form: &Ast, env: &[Scope], global: &mut Global) -> Result<BodyForm, SchemeError> {
+    match *form {
+        Ast::List(ref elems, ref last, ref span) if **last == Ast::Nil && !elems.is_empty() => {
+            if is_special_form(&elems[0], "define", env, global) {
+                Ok(BodyForm::Definition)
+            } else if elems.len() > 1 && is_special_form(&elems[0], "begin", env, global) {
+                Ok(BodyForm::Splice(elems[1..].to_vec()))
+            } else {
+                match form.expand(elems, env, global).map_err(|e| e.at(span.as_ref()))? {
+                    Some(expanded) => Ok(BodyForm::Expanded(expanded)),
+                    None => Ok(BodyForm::Expression),
+                }
+            }
+        }
+        _ => Ok(BodyForm::Expression),
+    }
+}
+
+// 本体の先頭に並んだ定義をletrec*として扱い、手続きの中だけで見える変数にする。
+// マクロが展開されてできた定義や、beginの中の定義も含める。
+fn compile_body(body: &[Ast],
+                env: &mut Env,
+                code: &mut MutableCode,
+                global: &mut Global,
+                tail: bool)
+                -> Result<(), SchemeError> {
+    // 先頭の式が上に来るスタック。展開していない式は本体をそのまま借りる。
+    let mut pending = body.iter().rev().map(Cow::Borrowed).collect::<Vec<_>>();
+    let mut forms = Vec::new();
+    let mut expansions = 0;
+    while let Some(form) = pending.pop() {
+        match body_form(&form, env, global)? {
+            BodyForm::Definition => {
+                forms.push(form);
+                expansions = 0;
+            }
+            BodyForm::Splice(elems) => pending.extend(elems.into_iter().rev().map(Cow::Owned)),
+            BodyForm::Expanded(expanded) => {
+                // 展開が終わらないマクロは、compile_helperで入れ子が深すぎる場合と同じく止める。
+                expansions += 1;
+                if expansions >= MAX_DEPTH {
+                    return Err(SchemeError::syntax("expression nested too deeply"));
+                }
+                pending.push(Cow::Owned(expanded));
+            }
+            BodyForm::Expression => {
+                pending.push(form);
+                break;
+            }
+        }
+    }
+    let mut defs = Vec::with_capacity(forms.len());
+    for form in &forms {
+        match **form {
+            Ast::List(ref elems, _, _) if elems.len() >= 3 => defs.push(definition(&elems[1], &elems[2..], env, global)?),
+            _ => return Err(SchemeError::syntax("malformed define")),
+        }
+    }
+    // 展開していない式は本体の末尾の部分なので、複製せずにそのまま使う。
+    let owned;
+    let rest = if pending.iter().all(|form| matches!(*form, Cow::Borrowed(_))) {
+        &body[body.len() - pending.len()..]
+    } else {
+        owned = pending.into_iter().rev().map(Cow::into_owned).collect::<Vec<_>>();
+        &owned[..]
+    };
+    if defs.is_empty() {
+        begin(rest, env, code, global, tail)
+    } else if rest.is_empty() {
+        Err(SchemeError::syntax("no expression after definitions"))
+    } else {
+        letrec(&defs, rest, env, code, global, tail)
+    }
+}
+
+// 変数をすべてUndefinedで束縛したフレームを作り、その中で初期値を順に評価して代入してから
+// 本体を評価する。((lambda (v ...) (set! v init) ... body ...) #<undefined> ...)と同じ。
+// 初期値は前から順に代入されるので、letrecもletrec*と同じ意味になる。
+fn letrec(defs: &[(&Ast, Init)],
           body: &[Ast],
           env: &mut Env,
           code: &mut MutableCode,
-          global: &Global)
-          -> Result<(), String> {
-    let mut new_env = env;
-    new_env.push(params);
-    let mut body_code = vec![CodeOp::Rtn];
-    begin(body, &mut new_env, &mut body_code, global)?;
-    code.push(CodeOp::Ldf(Rc::new(body_code.into_boxed_slice())));
+          global: &mut Global,
+          tail: bool)
+          -> Result<(), SchemeError> {
+    let names = defs.iter().map(|&(name, _)| name.to_owned()).collect::<Vec<_>>();
+    // 同じ名前を2度束縛すると、後の定義が先の変数を隠して見えなくなる。
+    for (i, name) in names.iter().enumerate() {
+        if names[..i].contains(name) {
+            return Err(SchemeError::syntax(&format!("duplicate binding: {}", name.identifier_name().unwrap_or(""))));
+        }
+    }
+    let mut body_code = code.nested(CodeOp::Rtn);
+    env.push(Scope::Vars(Ast::new_list(&names, Ast::Nil)));
+    let result = letrec_body(defs, body, env, &mut body_code, global);
+    env.pop();
+    result?;
+    let n = defs.len();
+    code.push(if tail { CodeOp::TApp(n) } else { CodeOp::App(n) });
+    code.push(CodeOp::Ldf(Rc::new(Lambda {
+                                      code: body_code.into_shared(),
+                                      name: None,
+                                      arity: Arity::Exactly(n),
+                                  })));
+    for _ in 0..n {
+        code.push(CodeOp::Ldc(Value::Undefined));
+    }
+    Ok(())
+}
+
+fn letrec_body(defs: &[(&Ast, Init)],
+               body: &[Ast],
+               env: &mut Env,
+               code: &mut MutableCode,
+               global: &mut Global)
+               -> Result<(), SchemeError> {
+    compile_body(body, env, code, global, true)?;
+    for (i, &(name, ref init)) in defs.iter().enumerate().rev() {
+        code.push(CodeOp::Pop);
+        code.push(CodeOp::Set((0, i)));
+        init.compile(name.identifier_name().unwrap_or(""), env, code, global)?;
+    }
     Ok(())
 }
 
@@ -165,53 +509,120 @@ fn if_(pred: &Ast,
        alt: Option<&Ast>,
        env: &mut Env,
        code: &mut MutableCode,
-       global: &Global)
-       -> Result<(), String> {
-    let mut conseq_code = vec![CodeOp::Join];
-    conseq.compile_helper(env, &mut conseq_code, global)?;
-    let mut alt_code = vec![CodeOp::Join];
+       global: &mut Global,
+       tail: bool)
+       -> Result<(), SchemeError> {
+    // 末尾位置では分岐先がそのまま呼び出し元に戻るので、JoinではなくRtnで終える。
+    let last = if tail { CodeOp::Rtn } else { CodeOp::Join };
+    let mut conseq_code = code.nested(last.to_owned());
+    conseq.compile_helper(env, &mut conseq_code, global, tail)?;
+    let mut alt_code = code.nested(last);
     alt.unwrap_or(&Ast::Undefined)
-        .compile_helper(env, &mut alt_code, global)?;
-    code.push(CodeOp::Sel(Rc::new(conseq_code.into_boxed_slice()),
-                          Rc::new(alt_code.into_boxed_slice())));
-    pred.compile_helper(env, code, global)?;
+        .compile_helper(env, &mut alt_code, global, tail)?;
+    let conseq_code = conseq_code.into_shared();
+    let alt_code = alt_code.into_shared();
+    if tail {
+        code.push(CodeOp::TSel(conseq_code, alt_code));
+    } else {
+        code.push(CodeOp::Sel(conseq_code, alt_code));
+    }
+    pred.compile_helper(env, code, global, false)?;
     Ok(())
 }
 
-fn apply(form: &[Ast], env: &mut Env, code: &mut MutableCode, global: &Global) -> Result<(), String> {
-    code.push(CodeOp::App(form[1..].len()));
-    form[0].compile_helper(env, code, global)?;
+fn apply(form: &[Ast],
+         env: &mut Env,
+         code: &mut MutableCode,
+         global: &mut Global,
+         tail: bool)
+         -> Result<(), SchemeError> {
+    let argc = form[1..].len();
+    code.push(if tail { CodeOp::TApp(argc) } else { CodeOp::App(argc) });
+    form[0].compile_helper(env, code, global, false)?;
     for ast in form[1..].iter().rev() {
-        ast.compile_helper(env, code, global)?;
+        ast.compile_helper(env, code, global, false)?;
     }
     Ok(())
 }
 
+fn set(var: &Ast,
+       value: &Ast,
+       env: &mut Env,
+       code: &mut MutableCode,
+       global: &mut Global)
+       -> Result<(), SchemeError> {
+    if var.identifier_name().is_none() {
+        return Err(SchemeError::syntax("malformed set!"));
+    }
+    match resolve(var, env) {
+        Binding::Local(location) => code.push(CodeOp::Set(location)),
+        Binding::Global(name) => code.push(CodeOp::Setg(global.slot(&name))),
+        Binding::Syntax(_) => return Err(SchemeError::syntax("malformed set!")),
+    }
+    value.compile_helper(env, code, global, false)
+}
+
 fn define(head: &Ast,
           tail: &[Ast],
           env: &mut Env,
           code: &mut MutableCode,
-          global: &Global)
-          -> Result<(), String> {
+          global: &mut Global)
+          -> Result<(), SchemeError> {
+    let (name, init) = definition(head, tail, env, global)?;
+    let name = name.identifier_name().unwrap_or("");
+    code.push(CodeOp::Def(global.slot(name)));
+    init.compile(name, env, code, global)
+}
+
+// 定義や束縛の初期値。名前の分かる手続きはLambdaとして持ち、その名前を付けてコンパイルする。
+enum Init<'a> {
+    Lambda(Ast, &'a [Ast]),
+    Value(&'a Ast),
+}
+
+impl<'a> Init<'a> {
+    // (define f (lambda ...))のような式も名前の分かる手続きとして扱う。
+    fn new(value: &'a Ast, env: &[Scope], global: &mut Global) -> Init<'a> {
+        match *value {
+            Ast::List(ref form, ref last, _) if **last == Ast::Nil && form.len() >= 2 &&
+                                             is_special_form(&form[0], "lambda", env, global) => {
+                Init::Lambda(form[1].to_owned(), &form[2..])
+            }
+            ref value => Init::Value(value),
+        }
+    }
+
+    fn compile(&self, name: &str, env: &mut Env, code: &mut MutableCode, global: &mut Global) -> Result<(), SchemeError> {
+        match *self {
+            Init::Lambda(ref params, body) => lambda(Some(name), params.to_owned(), body, env, code, global),
+            Init::Value(value) => value.compile_helper(env, code, global, false),
+        }
+    }
+}
+
+// (define name value)または(define (name . params) body ...)を名前と初期値に分ける。
+fn definition<'a>(head: &'a Ast,
+                  tail: &'a [Ast],
+                  env: &[Scope],
+                  global: &mut Global)
+                  -> Result<(&'a Ast, Init<'a>), SchemeError> {
     match *head {
-        Ast::Symbol(ref name) => {
+        Ast::Symbol(_) | Ast::Alias(_) => {
             if tail.len() != 1 {
-                return Err("malformed define".to_owned());
+                return Err(SchemeError::syntax("malformed define"));
             }
-            code.push(CodeOp::Def(name.to_owned()));
-            tail[0].compile_helper(env, code, global)?;
-            Ok(())
+            Ok((head, Init::new(&tail[0], env, global)))
         }
-        Ast::List(ref former, ref last) => {
-            if let Some(&Ast::Symbol(ref name)) = former.get(0) {
-                code.push(CodeOp::Def(name.to_owned()));
-                let params = Ast::new_list(&former[1..], *last.to_owned());
-                lambda(params, tail, env, code, global)
-            } else {
-                Err("malformed define".to_owned())
+        Ast::List(ref former, ref last, _) => {
+            match former.first() {
+                Some(name) if name.identifier_name().is_some() => {
+                    let params = Ast::new_list(&former[1..], *last.to_owned());
+                    Ok((name, Init::Lambda(params, tail)))
+                }
+                _ => Err(SchemeError::syntax("malformed define")),
             }
         }
-        _ => Err("malformed define".to_owned()),
+        _ => Err(SchemeError::syntax("malformed define")),
     }
 }
 
@@ -219,53 +630,149 @@ fn define_macro(head: &Ast,
                 tail: &[Ast],
                 env: &mut Env,
                 code: &mut MutableCode,
-                global: &Global)
-                -> Result<(), String> {
+                global: &mut Global)
+                -> Result<(), SchemeError> {
     match *head {
-        Ast::Symbol(ref name) => {
+        Ast::Symbol(_) | Ast::Alias(_) => {
             if tail.len() != 1 {
-                return Err("malformed define-macro".to_owned());
+                return Err(SchemeError::syntax("malformed define-macro"));
             }
-            code.push(CodeOp::Defm(name.to_owned()));
-            tail[0].compile_helper(env, code, global)?;
+            code.push(CodeOp::Defm(head.identifier_name().unwrap_or("").to_owned()));
+            tail[0].compile_helper(env, code, global, false)?;
             Ok(())
         }
-        Ast::List(ref former, ref last) => {
-            if let Some(&Ast::Symbol(ref name)) = former.get(0) {
+        Ast::List(ref former, ref last, _) => {
+            if let Some(name) = former.first().and_then(|name| name.identifier_name()) {
                 code.push(CodeOp::Defm(name.to_owned()));
                 let params = Ast::new_list(&former[1..], *last.to_owned());
-                lambda(params, tail, env, code, global)
+                lambda(Some(name), params, tail, env, code, global)
             } else {
-                Err("malformed define-macro".to_owned())
+                Err(SchemeError::syntax("malformed define-macro"))
+            }
+        }
+        _ => Err(SchemeError::syntax("malformed define-macro")),
+    }
+}
+
+// 大域的なマクロはトップレベルでのみ定義できる。
+fn define_syntax(name: &Ast, spec: &Ast, env: &mut Env, code: &mut MutableCode) -> Result<(), SchemeError> {
+    let name = name.identifier_name()
+        .ok_or_else(|| SchemeError::syntax("malformed define-syntax"))?;
+    if !env.is_empty() {
+        return Err(SchemeError::syntax("define-syntax is only allowed at toplevel"));
+    }
+    let rules = SyntaxRules::new(spec, env.len()).map_err(|e| SchemeError::syntax(&e))?;
+    code.push(CodeOp::Defs(name.to_owned(), Rc::new(rules)));
+    Ok(())
+}
+
+// let-syntaxのマクロは外側の環境で、letrec-syntaxのマクロは自身を含む環境で
+// テンプレート中の識別子を解決する。
+fn let_syntax(bindings: &Ast,
+              body: &[Ast],
+              env: &mut Env,
+              code: &mut MutableCode,
+              global: &mut Global,
+              tail: bool,
+              recursive: bool)
+              -> Result<(), SchemeError> {
+    let (bindings, last) = bindings.flatten();
+    if last != Ast::Nil {
+        return Err(SchemeError::syntax("malformed let-syntax"));
+    }
+    let env_len = if recursive { env.len() + 1 } else { env.len() };
+    let mut macros = Vec::with_capacity(bindings.len());
+    for binding in bindings {
+        match binding.flatten() {
+            (ref spec, Ast::Nil) if spec.len() == 2 && spec[0].identifier_name().is_some() => {
+                let rules = SyntaxRules::new(&spec[1], env_len).map_err(|e| SchemeError::syntax(&e))?;
+                macros.push((spec[0].to_owned(), Rc::new(rules)));
             }
+            _ => return Err(SchemeError::syntax("malformed let-syntax")),
         }
-        _ => Err("malformed define-macro".to_owned()),
     }
+    env.push(Scope::Macros(macros));
+    let result = begin(body, env, code, global, tail);
+    env.pop();
+    result
 }
 
-fn location(sym: &Ast, env: &[Ast]) -> Option<Location> {
-    for (i, frame) in env.iter().enumerate() {
-        if let Some(j) = position(sym, frame) {
-            return Some((i, j));
+// idが局所的な束縛やマクロに隠されていない特殊形式nameを指しているかどうか。
+fn is_special_form(id: &Ast, name: &str, env: &[Scope], global: &mut Global) -> bool {
+    match resolve(id, env) {
+        Binding::Global(ref global_name) if global_name == name => {
+            !matches!(global.get(name), Some(&Value::Macro(_, _)) | Some(&Value::Syntax(_)))
         }
+        _ => false,
+    }
+}
+
+fn resolve(id: &Ast, env: &[Scope]) -> Binding {
+    resolve_in(id, env, env.len())
+}
+
+// 識別子が束縛されている場所。局所的な束縛はenvの何番目のScopeの何番目か。
+#[derive(PartialEq)]
+enum Place {
+    Local(usize, usize),
+    Global(String),
+}
+
+// env[..len]の範囲で識別子の束縛を探す。リネームされた識別子が見つからなければ、
+// マクロを定義した時点の環境で元の識別子を探す。
+fn locate(id: &Ast, env: &[Scope], len: usize) -> Place {
+    // 内側のフレームから探すことで、外側の同名の変数を正しく隠す。
+    for (k, scope) in env[..len].iter().enumerate().rev() {
+        let found = match *scope {
+            Scope::Vars(ref params) => position(id, params),
+            Scope::Macros(ref macros) => macros.iter().position(|m| m.0 == *id),
+        };
+        if let Some(j) = found {
+            return Place::Local(k, j);
+        }
+    }
+    match *id {
+        Ast::Alias(ref alias) => locate(&alias.name, env, alias.env_len.min(len)),
+        _ => Place::Global(id.identifier_name().unwrap_or("").to_owned()),
     }
-    None
 }
 
-fn position(sym: &Ast, frame: &Ast) -> Option<Position> {
+fn resolve_in(id: &Ast, env: &[Scope], len: usize) -> Binding {
+    match locate(id, env, len) {
+        Place::Local(k, j) => {
+            match env[k] {
+                Scope::Vars(_) => {
+                    let depth = env[k + 1..]
+                        .iter()
+                        .filter(|scope| match **scope {
+                                    Scope::Vars(_) => true,
+                                    Scope::Macros(_) => false,
+                                })
+                        .count();
+                    Binding::Local((depth, j))
+                }
+                Scope::Macros(ref macros) => Binding::Syntax(macros[j].1.clone()),
+            }
+        }
+        Place::Global(name) => Binding::Global(name),
+    }
+}
+
+// 可変長引数はCodeOp::Restによってフレームの末尾の1要素にまとめられる。
+fn position(sym: &Ast, frame: &Ast) -> Option<usize> {
     match *frame {
-        Ast::List(ref vec, ref last) => {
+        Ast::List(ref vec, ref last, _) => {
             if let Some(i) = vec.iter().position(|x| sym == x) {
-                Some(Position::Index(i))
+                Some(i)
             } else if *sym == **last {
-                Some(Position::Rest(vec.len()))
+                Some(vec.len())
             } else {
                 None
             }
         }
-        Ast::Symbol(_) => {
+        Ast::Symbol(_) | Ast::Alias(_) => {
             if sym == frame {
-                Some(Position::Rest(0))
+                Some(0)
             } else {
                 None
             }
@@ -273,3 +780,11 @@ fn position(sym: &Ast, frame: &Ast) -> Option<Position> {
         _ => None,
     }
 }
+
+fn rest_index(params: &Ast) -> Option<usize> {
+    match *params {
+        Ast::List(ref vec, ref last, _) if **last != Ast::Nil => Some(vec.len()),
+        Ast::Symbol(_) | Ast::Alias(_) => Some(0),
+        _ => None,
+    }
+}
@@ -0,0 +1,373 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt::Display;
+use std::hash::Hash;
+use std::rc::Rc;
+use error::SchemeError;
+use native::Arity;
+use num::BigInt;
+use number::Number;
+use value::{CycleCheck, Pair, Value, Vector, vec2cons};
+
+/// Rustの値を`Value`に変換する。
+pub trait IntoValue {
+    fn into_value(self) -> Value;
+}
+
+/// `Value`をRustの値に変換する。変換できなければ、期待した型の名前を返す。
+pub trait FromValue: Sized {
+    fn from_value(value: &Value) -> Result<Self, String>;
+}
+
+impl IntoValue for Value {
+    fn into_value(self) -> Value {
+        self
+    }
+}
+
+impl FromValue for Value {
+    fn from_value(value: &Value) -> Result<Value, String> {
+        Ok(value.to_owned())
+    }
+}
+
+impl IntoValue for Number {
+    fn into_value(self) -> Value {
+        Value::Number(self)
+    }
+}
+
+impl FromValue for Number {
+    fn from_value(value: &Value) -> Result<Number, String> {
+        match *value {
+            Value::Number(ref n) => Ok(n.to_owned()),
+            _ => Err("number".to_owned()),
+        }
+    }
+}
+
+// i64に収まらない値はBigとして表す。Rustの値に戻すときは範囲を確かめ、
+// 収まらなければ期待した型の名前を返す。
+macro_rules! integer_conversion {
+    ($($t:ty => $expected:expr),*) => {
+        $(
+            impl IntoValue for $t {
+                fn into_value(self) -> Value {
+                    match i64::try_from(self) {
+                        Ok(i) => Value::integer(i),
+                        Err(_) => Value::Number(Number::big(BigInt::from(self))),
+                    }
+                }
+            }
+
+            impl FromValue for $t {
+                fn from_value(value: &Value) -> Result<$t, String> {
+                    let result = match *value {
+                        Value::Number(Number::Integer(i)) => <$t>::try_from(i).ok(),
+                        Value::Number(Number::Big(ref i)) => <$t>::try_from(&**i).ok(),
+                        _ => None,
+                    };
+                    result.ok_or_else(|| $expected.to_owned())
+                }
+            }
+        )*
+    }
+}
+
+integer_conversion!(i8 => "8-bit integer",
+                    i16 => "16-bit integer",
+                    i32 => "32-bit integer",
+                    i64 => "integer",
+                    u8 => "byte",
+                    u16 => "16-bit unsigned integer",
+                    u32 => "32-bit unsigned integer",
+                    u64 => "64-bit unsigned integer",
+                    usize => "non-negative integer");
+
+impl IntoValue for f64 {
+    fn into_value(self) -> Value {
+        Value::Number(Number::Real(self))
+    }
+}
+
+impl FromValue for f64 {
+    fn from_value(value: &Value) -> Result<f64, String> {
+        match *value {
+            Value::Number(ref n) => Ok(n.to_f64()),
+            _ => Err("number".to_owned()),
+        }
+    }
+}
+
+impl IntoValue for bool {
+    fn into_value(self) -> Value {
+        Value::Boolean(self)
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: &Value) -> Result<bool, String> {
+        match *value {
+            Value::Boolean(b) => Ok(b),
+            _ => Err("boolean".to_owned()),
+        }
+    }
+}
+
+impl IntoValue for char {
+    fn into_value(self) -> Value {
+        Value::Char(self)
+    }
+}
+
+impl FromValue for char {
+    fn from_value(value: &Value) -> Result<char, String> {
+        match *value {
+            Value::Char(c) => Ok(c),
+            _ => Err("character".to_owned()),
+        }
+    }
+}
+
+impl IntoValue for String {
+    fn into_value(self) -> Value {
+        Value::string(&self)
+    }
+}
+
+impl IntoValue for &str {
+    fn into_value(self) -> Value {
+        Value::string(self)
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: &Value) -> Result<String, String> {
+        match *value {
+            Value::String(ref s) => Ok(s.as_ref().to_owned()),
+            _ => Err("string".to_owned()),
+        }
+    }
+}
+
+// ペアやベクタはそのまま共有する。書き換えると元の値も変わる。
+impl FromValue for Rc<Pair> {
+    fn from_value(value: &Value) -> Result<Rc<Pair>, String> {
+        match *value {
+            Value::Cell(ref pair) => Ok(pair.clone()),
+            _ => Err("pair".to_owned()),
+        }
+    }
+}
+
+impl FromValue for Rc<Vector<Value>> {
+    fn from_value(value: &Value) -> Result<Rc<Vector<Value>>, String> {
+        match *value {
+            Value::Vector(ref v) => Ok(v.clone()),
+            _ => Err("vector".to_owned()),
+        }
+    }
+}
+
+impl FromValue for Rc<Vector<u8>> {
+    fn from_value(value: &Value) -> Result<Rc<Vector<u8>>, String> {
+        match *value {
+            Value::Bytevector(ref b) => Ok(b.clone()),
+            _ => Err("bytevector".to_owned()),
+        }
+    }
+}
+
+// Vecはリストとして表す。
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn into_value(self) -> Value {
+        let values = self.into_iter().map(IntoValue::into_value).collect::<Vec<_>>();
+        vec2cons(&values, Value::Nil)
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: &Value) -> Result<Vec<T>, String> {
+        let mut result = Vec::new();
+        let mut list = value.to_owned();
+        let mut cycle = CycleCheck::new(&list);
+        while let Value::Cell(cell) = list {
+            result.push(T::from_value(&cell.car()).map_err(|e| format!("list of {}", e))?);
+            list = cell.cdr();
+            if cycle.step(&list) {
+                return Err("proper list".to_owned());
+            }
+        }
+        if list != Value::Nil {
+            return Err("proper list".to_owned());
+        }
+        Ok(result)
+    }
+}
+
+// Noneは#fとして表す。
+impl<T: IntoValue> IntoValue for Option<T> {
+    fn into_value(self) -> Value {
+        match self {
+            Some(v) => v.into_value(),
+            None => Value::Boolean(false),
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: &Value) -> Result<Option<T>, String> {
+        match *value {
+            Value::Boolean(false) => Ok(None),
+            ref v => T::from_value(v).map(Some).map_err(|e| format!("{} or #f", e)),
+        }
+    }
+}
+
+// HashMapは(key . value)を要素とする連想リストとして表す。
+impl<K: IntoValue + Eq + Hash, V: IntoValue> IntoValue for HashMap<K, V> {
+    fn into_value(self) -> Value {
+        let values = self.into_iter()
+            .map(|(k, v)| Value::cons(k.into_value(), v.into_value()))
+            .collect::<Vec<_>>();
+        vec2cons(&values, Value::Nil)
+    }
+}
+
+impl<K: FromValue + Eq + Hash, V: FromValue> FromValue for HashMap<K, V> {
+    fn from_value(value: &Value) -> Result<HashMap<K, V>, String> {
+        let entries = Vec::<Value>::from_value(value)?;
+        let mut result = HashMap::with_capacity(entries.len());
+        for entry in entries {
+            match entry {
+                Value::Cell(ref cell) => {
+                    let k = K::from_value(&cell.car()).map_err(|e| format!("association list of {} keys", e))?;
+                    let v = V::from_value(&cell.cdr()).map_err(|e| format!("association list of {} values", e))?;
+                    result.insert(k, v);
+                }
+                _ => return Err("association list".to_owned()),
+            }
+        }
+        Ok(result)
+    }
+}
+
+// タプルは要素数が同じリストとして表す。
+macro_rules! tuple_conversion {
+    ($len:expr; $($t:ident),*) => {
+        impl<$($t: IntoValue),*> IntoValue for ($($t,)*) {
+            #[allow(non_snake_case)]
+            fn into_value(self) -> Value {
+                let ($($t,)*) = self;
+                vec2cons(&[$($t.into_value()),*], Value::Nil)
+            }
+        }
+
+        impl<$($t: FromValue),*> FromValue for ($($t,)*) {
+            fn from_value(value: &Value) -> Result<($($t,)*), String> {
+                let values = Vec::<Value>::from_value(value)?;
+                if values.len() != $len {
+                    return Err(format!("list of length {}", $len));
+                }
+                let mut values = values.iter();
+                Ok(($($t::from_value(values.next().unwrap_or(&Value::Undefined))?,)*))
+            }
+        }
+    }
+}
+
+tuple_conversion!(1; A);
+tuple_conversion!(2; A, B);
+tuple_conversion!(3; A, B, C);
+tuple_conversion!(4; A, B, C, D);
+
+/// 手続きの戻り値。`IntoValue`を実装する型の値か、エラーを表示できる`Result`を返せる。
+pub trait IntoResult {
+    fn into_result(self) -> Result<Value, SchemeError>;
+}
+
+impl<T: IntoValue, E: Display> IntoResult for Result<T, E> {
+    fn into_result(self) -> Result<Value, SchemeError> {
+        self.map(IntoValue::into_value)
+            .map_err(|e| SchemeError::runtime(&e.to_string()))
+    }
+}
+
+macro_rules! into_result {
+    ($(impl<$($p:ident),*> for $t:ty;)*) => {
+        $(
+            impl<$($p: IntoValue),*> IntoResult for $t {
+                fn into_result(self) -> Result<Value, SchemeError> {
+                    Ok(self.into_value())
+                }
+            }
+        )*
+    }
+}
+
+into_result! {
+    impl<> for Value;
+    impl<> for Number;
+    impl<> for i8;
+    impl<> for i16;
+    impl<> for i32;
+    impl<> for i64;
+    impl<> for u8;
+    impl<> for u16;
+    impl<> for u32;
+    impl<> for u64;
+    impl<> for usize;
+    impl<> for f64;
+    impl<> for bool;
+    impl<> for char;
+    impl<> for String;
+    impl<> for &'static str;
+    impl<T> for Vec<T>;
+    impl<T> for Option<T>;
+    impl<A> for (A,);
+    impl<A, B> for (A, B);
+    impl<A, B, C> for (A, B, C);
+    impl<A, B, C, D> for (A, B, C, D);
+}
+
+impl<K: IntoValue + Eq + Hash, V: IntoValue> IntoResult for HashMap<K, V> {
+    fn into_result(self) -> Result<Value, SchemeError> {
+        Ok(self.into_value())
+    }
+}
+
+/// 型付きのRustの関数を手続きにする。引数の数は関数の仮引数から決まり、
+/// 各引数は`FromValue`で変換される。変換できない引数は型のエラーになる。
+pub trait IntoProcedure<Args> {
+    fn into_procedure(self, name: &str) -> Value;
+}
+
+macro_rules! into_procedure {
+    ($len:expr; $($t:ident),*) => {
+        impl<F, R, $($t),*> IntoProcedure<($($t,)*)> for F
+            where F: Fn($($t),*) -> R + 'static,
+                  R: IntoResult,
+                  $($t: FromValue),*
+        {
+            #[allow(non_snake_case, unused_variables, unused_mut)]
+            fn into_procedure(self, name: &str) -> Value {
+                let owned_name = name.to_owned();
+                Value::native(name, Arity::Exactly($len), move |args| {
+                    let mut args = args.iter();
+                    $(
+                        let arg = args.next().unwrap_or(&Value::Undefined);
+                        let $t = $t::from_value(arg)
+                            .map_err(|e| SchemeError::wrong_type(&e, &owned_name, arg))?;
+                    )*
+                    self($($t),*).into_result()
+                })
+            }
+        }
+    }
+}
+
+into_procedure!(0;);
+into_procedure!(1; A);
+into_procedure!(2; A, B);
+into_procedure!(3; A, B, C);
+into_procedure!(4; A, B, C, D);
@@ -0,0 +1,200 @@
+use std::error;
+use std::fmt;
+use limits::Limit;
+use native::Arity;
+use span::Span;
+use value::{CycleCheck, Value, vec2cons};
+
+/// 実行時やコンパイル時に起きるエラーの種類。型や引数の数の誤りでは、
+/// 問題になった手続きの名前と値を持つ。
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorKind {
+    UnboundVariable(String),
+    Arity {
+        procedure: String,
+        expected: Option<Arity>,
+        got: usize,
+    },
+    Type {
+        expected: String,
+        procedure: String,
+        value: Value,
+    },
+    Syntax(String),
+    // 捕捉されなかったraise。errorによるエラーオブジェクトもここに含まれる。
+    Raise(Value),
+    Runtime(String),
+    // Limitsで決めた資源の上限に達した。例外ハンドラでは捕捉できない。
+    LimitExceeded(Limit),
+    // エラーではなく、プリミティブがホストに制御を返すよう求めたもの。
+    // Interpreter::startで始めた評価では中断になり、それ以外ではエラーになる。
+    Yield(Value),
+}
+
+/// エラーの種類と、エラーが起きた式の位置、その時点で実行中だった手続きの名前の列。
+/// バックトレースは内側の手続きから順に並び、末尾呼び出しで置き換えられたフレームは含まない。
+/// 同じ手続きが続く部分は`f ... N more`のように1つにまとめ、長すぎる分は数だけを示す。
+/// `Result`のエラーとして持ち回るので、種類は箱に入れておく。
+#[derive(Debug, Clone, PartialEq)]
+pub struct SchemeError {
+    pub kind: Box<ErrorKind>,
+    pub span: Option<Span>,
+    pub backtrace: Vec<String>,
+}
+
+impl SchemeError {
+    pub fn new(kind: ErrorKind) -> SchemeError {
+        SchemeError {
+            kind: Box::new(kind),
+            span: None,
+            backtrace: Vec::new(),
+        }
+    }
+
+    pub fn unbound_variable(name: &str) -> SchemeError {
+        SchemeError::new(ErrorKind::UnboundVariable(name.to_owned()))
+    }
+
+    pub fn arity(procedure: &str, got: usize) -> SchemeError {
+        SchemeError::new(ErrorKind::Arity {
+                             procedure: procedure.to_owned(),
+                             expected: None,
+                             got,
+                         })
+    }
+
+    pub fn arity_expected(procedure: &str, expected: Arity, got: usize) -> SchemeError {
+        SchemeError::new(ErrorKind::Arity {
+                             procedure: procedure.to_owned(),
+                             expected: Some(expected),
+                             got,
+                         })
+    }
+
+    pub fn wrong_type(expected: &str, procedure: &str, value: &Value) -> SchemeError {
+        SchemeError::new(ErrorKind::Type {
+                             expected: expected.to_owned(),
+                             procedure: procedure.to_owned(),
+                             value: value.to_owned(),
+                         })
+    }
+
+    pub fn syntax(message: &str) -> SchemeError {
+        SchemeError::new(ErrorKind::Syntax(message.to_owned()))
+    }
+
+    pub fn runtime(message: &str) -> SchemeError {
+        SchemeError::new(ErrorKind::Runtime(message.to_owned()))
+    }
+
+    pub fn limit_exceeded(limit: Limit) -> SchemeError {
+        SchemeError::new(ErrorKind::LimitExceeded(limit))
+    }
+
+    /// プリミティブから返すと評価を中断し、`value`をホストに渡す。
+    /// ホストが`Interpreter::resume`に渡した値がプリミティブの戻り値になる。
+    pub fn yield_to_host(value: Value) -> SchemeError {
+        SchemeError::new(ErrorKind::Yield(value))
+    }
+
+    /// 位置が分かっていなければspanを位置とする。入れ子の式では最も内側の位置が残る。
+    pub fn at(mut self, span: Option<&Span>) -> SchemeError {
+        if self.span.is_none() {
+            self.span = span.cloned();
+        }
+        self
+    }
+
+    /// エラーの内容を表す文字列。エラーオブジェクトのメッセージになる。
+    pub fn message(&self) -> String {
+        match *self.kind {
+            ErrorKind::UnboundVariable(ref name) => format!("unbound variable: {}", name),
+            ErrorKind::Arity { ref procedure, expected: Some(expected), got } => {
+                format!("expected {}, got {} in {}", expected, got, procedure)
+            }
+            ErrorKind::Arity { ref procedure, expected: None, .. } => {
+                format!("wrong number of arguments: {}", procedure)
+            }
+            ErrorKind::Type { ref expected, ref procedure, .. } => {
+                format!("{} required: {}", expected, procedure)
+            }
+            ErrorKind::Syntax(ref message) |
+            ErrorKind::Runtime(ref message) => message.to_owned(),
+            ErrorKind::Raise(Value::Error(ref error)) => format!("{}", error.0.display()),
+            ErrorKind::Raise(ref obj) => format!("uncaught exception: {}", obj),
+            ErrorKind::LimitExceeded(limit) => {
+                let resource = match limit {
+                    Limit::Steps => "step",
+                    Limit::Time => "time",
+                    Limit::DumpDepth => "dump depth",
+                    Limit::Heap => "heap",
+                };
+                format!("{} limit exceeded", resource)
+            }
+            ErrorKind::Yield(ref value) => format!("cannot yield outside of a resumable evaluation: {}", value),
+        }
+    }
+
+    /// エラーの原因になった値。
+    pub fn irritants(&self) -> Vec<Value> {
+        match *self.kind {
+            ErrorKind::Type { ref value, .. } => vec![value.to_owned()],
+            ErrorKind::Raise(Value::Error(ref error)) => {
+                let mut irritants = Vec::new();
+                let mut list = error.1.to_owned();
+                let mut cycle = CycleCheck::new(&list);
+                while let Value::Cell(cell) = list {
+                    irritants.push(cell.car());
+                    list = cell.cdr();
+                    if cycle.step(&list) {
+                        break;
+                    }
+                }
+                irritants
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// 例外ハンドラに渡すオブジェクト。raiseされた値はそのまま渡し、
+    /// それ以外はメッセージと原因の値を持つエラーオブジェクトにする。
+    pub fn to_object(&self) -> Value {
+        match *self.kind {
+            ErrorKind::Raise(ref obj) => obj.to_owned(),
+            _ => Value::error(Value::string(&self.message()), vec2cons(&self.irritants(), Value::Nil)),
+        }
+    }
+}
+
+impl fmt::Display for SchemeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message())?;
+        for irritant in self.irritants() {
+            write!(f, " {}", irritant)?;
+        }
+        if let Some(ref span) = self.span {
+            write!(f, "\n  at {}", span)?;
+            if let Some((line, caret)) = span.snippet() {
+                write!(f, "\n    {}\n    {}", line, caret)?;
+            }
+        }
+        for name in &self.backtrace {
+            write!(f, "\n    in {}", name)?;
+        }
+        Ok(())
+    }
+}
+
+impl error::Error for SchemeError {}
+
+impl From<String> for SchemeError {
+    fn from(message: String) -> SchemeError {
+        SchemeError::new(ErrorKind::Runtime(message))
+    }
+}
+
+impl From<&str> for SchemeError {
+    fn from(message: &str) -> SchemeError {
+        SchemeError::runtime(message)
+    }
+}
@@ -0,0 +1,357 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+use error::SchemeError;
+use limits::Limit;
+use value::{Pair, Value, Vector};
+use vm::{Continuation, Env, Frame};
+
+// 循環した構造を回収するコレクタ。値はRcで管理されているので、循環していないものは
+// 参照がなくなった時点で解放される。コレクタは循環を含みうるオブジェクト(ペア、ベクタ、
+// フレーム、継続)をWeakで登録しておき、ときどき次の手順で到達できない循環を壊す。
+//
+// 1. 登録されたオブジェクトどうしの参照の数を、各オブジェクトの強参照の数から引く。
+// 2. 残りが正のオブジェクトは、stack、env、dump、Globalやライブラリの利用者が持つ値など、
+//    ヒープの外から参照されている。これらを根として、そこから到達できるものに印を付ける。
+// 3. 印の付かなかったペア、ベクタ、フレームの中身を空にして循環を断ち切る。
+//
+// 文字列とバイトベクタは循環を作らないが、ヒープの上限で大きさを数えるために同じく登録する。
+//
+// 登録先のHeapはインタプリタごとに持ち、他のインタプリタが作ったものは回収の対象にも
+// 大きさの合計にも含めない。プリミティブは環境を受け取らずに値を作るので、VMは実行の間だけ
+// 自分のHeapをスレッドの「現在のヒープ」にしておき、登録はそこに対して行う。評価の外で
+// 作られた値はどこにも登録されないが、外から参照されている根として扱われるだけで安全である。
+//
+// オブジェクトの大きさは、ペアと継続を1、ベクタとフレームを1と要素の数の和、文字列と
+// バイトベクタを1と8バイトごとに1の和として数える。
+thread_local! {
+    static CURRENT: RefCell<Option<Rc<Heap>>> = const { RefCell::new(None) };
+}
+
+// 前回の回収後に登録されたオブジェクトの大きさの合計が、この値と生き残ったものの
+// 大きさの合計の大きい方を超えたら回収する。
+const MIN_THRESHOLD: usize = 100_000;
+
+/// インタプリタ1つ分の、循環を含みうるオブジェクトの登録簿。
+#[derive(Debug)]
+pub struct Heap {
+    objects: RefCell<Vec<(Object, usize)>>,
+    // 登録されたオブジェクトのうち、解放されていない可能性のあるものの大きさの合計。
+    size: Cell<usize>,
+    // 前回の回収の後に登録されたものの大きさの合計。
+    allocated: Cell<usize>,
+    threshold: Cell<usize>,
+    limit: Cell<Option<usize>>,
+}
+
+/// `enter`で切り替えた現在のヒープを、破棄されたときに元に戻す。
+pub struct Entered {
+    previous: Option<Rc<Heap>>,
+}
+
+/// 登録されたオブジェクトが直接持つ値や環境。traceでコレクタに渡す。
+pub enum Child<'a> {
+    Value(&'a Value),
+    Env(&'a Env),
+}
+
+#[derive(Debug)]
+enum Object {
+    Pair(Weak<Pair>),
+    Vector(Weak<Vector<Value>>),
+    Frame(Weak<Frame>),
+    Continuation(Weak<Continuation>),
+    String(Weak<String>),
+    Bytevector(Weak<Vector<u8>>),
+}
+
+// 回収の間だけ強参照として保持するオブジェクト。回収中に解放されないようにする。
+enum Live {
+    Pair(Rc<Pair>),
+    Vector(Rc<Vector<Value>>),
+    Frame(Rc<Frame>),
+    Continuation(Rc<Continuation>),
+    String(Rc<String>),
+    Bytevector(Rc<Vector<u8>>),
+}
+
+/// heapを現在のヒープにする。戻り値を破棄すると元に戻る。
+pub fn enter(heap: &Rc<Heap>) -> Entered {
+    let previous = CURRENT.with(|current| current.replace(Some(heap.clone())));
+    Entered { previous }
+}
+
+impl Drop for Entered {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        CURRENT.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+pub fn register_pair(pair: &Rc<Pair>) {
+    register(Object::Pair(Rc::downgrade(pair)), 1);
+}
+
+pub fn register_vector(vector: &Rc<Vector<Value>>) {
+    register(Object::Vector(Rc::downgrade(vector)), 1 + vector.borrow().len());
+}
+
+pub fn register_frame(frame: &Rc<Frame>) {
+    register(Object::Frame(Rc::downgrade(frame)), 1 + frame.size());
+}
+
+pub fn register_continuation(k: &Rc<Continuation>) {
+    register(Object::Continuation(Rc::downgrade(k)), 1);
+}
+
+pub fn register_string(s: &Rc<String>) {
+    register(Object::String(Rc::downgrade(s)), words(s.len()));
+}
+
+pub fn register_bytevector(bytes: &Rc<Vector<u8>>) {
+    register(Object::Bytevector(Rc::downgrade(bytes)), words(bytes.borrow().len()));
+}
+
+/// bytesバイトの文字列やバイトベクタの大きさ。
+pub fn words(bytes: usize) -> usize {
+    1 + bytes.div_ceil(8)
+}
+
+/// 大きさsizeのものを作っても現在のヒープの上限を超えないか確かめる。作る前に呼ぶので、
+/// 大きすぎる確保を試みずにエラーにできる。評価の外では何もしない。
+pub fn reserve(size: usize) -> Result<(), SchemeError> {
+    let heap = CURRENT.with(|current| current.borrow().clone());
+    match heap {
+        Some(heap) => heap.reserve(size),
+        None => Ok(()),
+    }
+}
+
+fn register(object: Object, size: usize) {
+    CURRENT.with(|current| if let Some(ref heap) = *current.borrow() {
+                     heap.objects.borrow_mut().push((object, size));
+                     heap.size.set(heap.size.get() + size);
+                     heap.allocated.set(heap.allocated.get() + size);
+                 });
+}
+
+impl Default for Heap {
+    fn default() -> Heap {
+        Heap {
+            objects: RefCell::new(Vec::new()),
+            size: Cell::new(0),
+            allocated: Cell::new(0),
+            threshold: Cell::new(MIN_THRESHOLD),
+            limit: Cell::new(None),
+        }
+    }
+}
+
+impl Heap {
+    /// 前回の回収から十分な大きさのオブジェクトが作られていれば真。
+    pub fn should_collect(&self) -> bool {
+        self.allocated.get() >= self.threshold.get()
+    }
+
+    /// 生きている可能性のあるオブジェクトの大きさの合計。解放済みのものも含む上限で、
+    /// 回収の直後は生きているものの合計に一致する。
+    pub fn size(&self) -> usize {
+        self.size.get()
+    }
+
+    /// 大きさの合計の上限。評価の資源の上限に合わせて設定する。
+    pub fn set_limit(&self, limit: Option<usize>) {
+        self.limit.set(limit);
+    }
+
+    /// 大きさsizeのものを加えても上限を超えないか確かめる。sizeは解放済みのものも含むので、
+    /// 超えるときはまず解放済みのものを除いて数え直し、それでも超えるときだけ循環を回収する。
+    /// 上限の近くで一時的な値を作り続けても、命令ごとに回収を繰り返さないようにするため。
+    pub fn reserve(&self, size: usize) -> Result<(), SchemeError> {
+        let max = match self.limit.get() {
+            Some(max) => max,
+            None => return Ok(()),
+        };
+        if self.size().saturating_add(size) <= max {
+            return Ok(());
+        }
+        self.prune();
+        if self.size().saturating_add(size) <= max {
+            return Ok(());
+        }
+        self.collect();
+        if self.size().saturating_add(size) <= max {
+            return Ok(());
+        }
+        Err(SchemeError::limit_exceeded(Limit::Heap))
+    }
+
+    // 解放済みのオブジェクトを登録簿から除き、大きさの合計を数え直す。
+    fn prune(&self) {
+        let mut objects = self.objects.borrow_mut();
+        objects.retain(|(object, _)| object.is_alive());
+        self.size.set(objects.iter().map(|&(_, size)| size).sum());
+    }
+
+    /// 到達できない循環を壊し、中身を空にしたオブジェクトの数を返す。
+    /// RefCellの借用が残っていてはいけないので、VMの命令の合間に呼ぶ。
+    pub fn collect(&self) -> usize {
+        let live = {
+            let mut objects = self.objects.borrow_mut();
+            objects.retain(|(object, _)| object.is_alive());
+            objects.iter()
+                .filter_map(|(object, size)| object.upgrade().map(|live| (live, *size)))
+                .collect::<Vec<_>>()
+        };
+        let index = live.iter()
+            .enumerate()
+            .map(|(i, (object, _))| (object.key(), i))
+            .collect::<HashMap<_, _>>();
+
+        // upgradeで1つ増えているので、その分を引いておく。
+        let mut external = live.iter().map(|(object, _)| object.strong_count() - 1).collect::<Vec<_>>();
+        let mut reachable = vec![false; live.len()];
+        let mut pending = Vec::new();
+        for (i, (object, _)) in live.iter().enumerate() {
+            let traced = object.trace(&mut |key| if let Some(&j) = index.get(&key) {
+                                                  external[j] -= 1;
+                                              });
+            // 借用中で中身を調べられないものは、念のため外から参照されているものとして扱う。
+            if !traced {
+                reachable[i] = true;
+                pending.push(i);
+            }
+        }
+        for (i, &count) in external.iter().enumerate() {
+            if count > 0 && !reachable[i] {
+                reachable[i] = true;
+                pending.push(i);
+            }
+        }
+        while let Some(i) = pending.pop() {
+            live[i].0.trace(&mut |key| if let Some(&j) = index.get(&key) {
+                                      if !reachable[j] {
+                                          reachable[j] = true;
+                                          pending.push(j);
+                                      }
+                                  });
+        }
+
+        let mut freed = 0;
+        let mut survived = 0;
+        for ((object, size), &reachable) in live.iter().zip(&reachable) {
+            if reachable {
+                survived += *size;
+            } else {
+                object.clear();
+                freed += 1;
+            }
+        }
+        self.allocated.set(0);
+        self.size.set(survived);
+        self.threshold.set(survived.max(MIN_THRESHOLD));
+        // ここでliveを捨てると、中身を空にしたオブジェクトが解放される。
+        freed
+    }
+}
+
+impl Object {
+    fn is_alive(&self) -> bool {
+        match *self {
+            Object::Pair(ref w) => w.strong_count() > 0,
+            Object::Vector(ref w) => w.strong_count() > 0,
+            Object::Frame(ref w) => w.strong_count() > 0,
+            Object::Continuation(ref w) => w.strong_count() > 0,
+            Object::String(ref w) => w.strong_count() > 0,
+            Object::Bytevector(ref w) => w.strong_count() > 0,
+        }
+    }
+
+    fn upgrade(&self) -> Option<Live> {
+        match *self {
+            Object::Pair(ref w) => w.upgrade().map(Live::Pair),
+            Object::Vector(ref w) => w.upgrade().map(Live::Vector),
+            Object::Frame(ref w) => w.upgrade().map(Live::Frame),
+            Object::Continuation(ref w) => w.upgrade().map(Live::Continuation),
+            Object::String(ref w) => w.upgrade().map(Live::String),
+            Object::Bytevector(ref w) => w.upgrade().map(Live::Bytevector),
+        }
+    }
+}
+
+impl Live {
+    fn key(&self) -> usize {
+        match *self {
+            Live::Pair(ref rc) => Rc::as_ptr(rc) as usize,
+            Live::Vector(ref rc) => Rc::as_ptr(rc) as usize,
+            Live::Frame(ref rc) => Rc::as_ptr(rc) as usize,
+            Live::Continuation(ref rc) => Rc::as_ptr(rc) as usize,
+            Live::String(ref rc) => Rc::as_ptr(rc) as usize,
+            Live::Bytevector(ref rc) => Rc::as_ptr(rc) as usize,
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match *self {
+            Live::Pair(ref rc) => Rc::strong_count(rc),
+            Live::Vector(ref rc) => Rc::strong_count(rc),
+            Live::Frame(ref rc) => Rc::strong_count(rc),
+            Live::Continuation(ref rc) => Rc::strong_count(rc),
+            Live::String(ref rc) => Rc::strong_count(rc),
+            Live::Bytevector(ref rc) => Rc::strong_count(rc),
+        }
+    }
+
+    // 直接参照しているオブジェクトをvisitに渡す。借用中で調べられなければfalse。
+    fn trace(&self, visit: &mut dyn FnMut(usize)) -> bool {
+        match *self {
+            Live::Pair(ref pair) => pair.trace(&mut |child| trace_child(child, visit)),
+            Live::Vector(ref vector) => {
+                match vector.try_borrow() {
+                    Ok(values) => {
+                        for v in values.iter() {
+                            trace_child(Child::Value(v), visit);
+                        }
+                        true
+                    }
+                    Err(_) => false,
+                }
+            }
+            Live::Frame(ref frame) => frame.trace(&mut |child| trace_child(child, visit)),
+            Live::Continuation(ref k) => {
+                k.trace(&mut |child| trace_child(child, visit));
+                true
+            }
+            Live::String(_) | Live::Bytevector(_) => true,
+        }
+    }
+
+    // 継続は書き換えできないが、循環は必ずペア、ベクタ、フレームのいずれかを含むので、
+    // それらを空にすれば継続も解放される。
+    fn clear(&self) {
+        match *self {
+            Live::Pair(ref pair) => pair.clear(),
+            Live::Vector(ref vector) => vector.clear(),
+            Live::Frame(ref frame) => frame.clear(),
+            Live::Continuation(_) | Live::String(_) | Live::Bytevector(_) => (),
+        }
+    }
+}
+
+// 値や環境が直接持つ、登録の対象になりうるオブジェクトへの参照。
+fn trace_child(child: Child, visit: &mut dyn FnMut(usize)) {
+    let env = match child {
+        Child::Value(Value::Cell(pair)) => return visit(Rc::as_ptr(pair) as usize),
+        Child::Value(Value::Vector(vector)) => return visit(Rc::as_ptr(vector) as usize),
+        Child::Value(Value::Continuation(k)) => return visit(Rc::as_ptr(k) as usize),
+        Child::Value(Value::String(s)) => return visit(Rc::as_ptr(s) as usize),
+        Child::Value(Value::Bytevector(bytes)) => return visit(Rc::as_ptr(bytes) as usize),
+        Child::Value(Value::Closure(_, env)) |
+        Child::Value(Value::Macro(_, env)) |
+        Child::Env(env) => env,
+        Child::Value(_) => return,
+    };
+    if let Some(ref frame) = *env {
+        visit(Rc::as_ptr(frame) as usize);
+    }
+}
@@ -0,0 +1,259 @@
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::mem;
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::vec;
+use compiler::Ast;
+use convert::IntoProcedure;
+use error::SchemeError;
+use limits::{Budget, Limits};
+use native::Arity;
+use primitive::define_primitives;
+use reader::read_source;
+use span::{Source, Span};
+use value::Value;
+use vm::{Code, Global, Machine, Status};
+
+// ライブラリとして組み込む場合にも読み込めるよう、プレリュードはバイナリに埋め込む。
+const PRELUDE: &str = include_str!("../lib/base.scm");
+
+/// 読み込みから実行までの各段階で起きるエラー。
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Read(String),
+    Scheme(SchemeError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref e) => write!(f, "I/O error: {}", e),
+            Error::Read(ref message) => write!(f, "read error: {}", message),
+            Error::Scheme(ref e) => write!(f, "error: {}", e),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Io(ref e) => Some(e),
+            Error::Scheme(ref e) => Some(e),
+            Error::Read(_) => None,
+        }
+    }
+}
+
+impl From<SchemeError> for Error {
+    fn from(e: SchemeError) -> Error {
+        Error::Scheme(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+/// `Interpreter::start`や`resume`で進めた評価の結果。
+#[allow(clippy::large_enum_variant)]
+pub enum Execution {
+    Done(Value),
+    Suspended(Suspended),
+}
+
+/// 中断した評価。実行中の`Machine`のstack、env、code、dumpと、まだ評価していない式を持つ。
+/// 中断したインタプリタの`resume`で再開する。
+pub struct Suspended {
+    owner: usize,
+    machine: Machine,
+    rest: vec::IntoIter<Ast>,
+    budget: Budget,
+    yielded: Option<Value>,
+}
+
+impl Suspended {
+    /// プリミティブが中断を求めたときに渡した値。命令の数で中断した場合はNone。
+    pub fn yielded(&self) -> Option<&Value> {
+        self.yielded.as_ref()
+    }
+}
+
+/// グローバル環境を持ち、式を評価するインタプリタ。
+/// 評価の間で環境は共有されるので、前に定義した変数や手続きを後から使える。
+pub struct Interpreter {
+    id: usize,
+    global: Global,
+    limits: Limits,
+}
+
+// 中断した評価を作ったインタプリタを見分けるための番号。
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+impl Interpreter {
+    /// プリミティブを登録し、プレリュード(lib/base.scm)を読み込んだインタプリタを作る。
+    pub fn new() -> Result<Interpreter, Error> {
+        let mut interpreter = Interpreter {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            global: define_primitives(),
+            limits: Limits::default(),
+        };
+        interpreter.eval_source("lib/base.scm", PRELUDE)?;
+        Ok(interpreter)
+    }
+
+    /// `input`に含まれる式を順に評価し、最後の式の値を返す。式がなければ未定義値を返す。
+    pub fn eval_str(&mut self, input: &str) -> Result<Value, Error> {
+        self.eval_source("<string>", input)
+    }
+
+    /// `eval_str`と同じだが、エラーの位置を`name`のテキストとして示す。
+    pub fn eval_source(&mut self, name: &str, input: &str) -> Result<Value, Error> {
+        let ast = read(name, input)?;
+        self.global.replace_budget(Budget::new(self.limits));
+        let mut result = Value::Undefined;
+        for exp in ast {
+            let code = exp.compile(&mut self.global)?;
+            result = Machine::run(None, code, &mut self.global)?;
+        }
+        Ok(result)
+    }
+
+    /// `eval_source`と同じだが、`steps`個の命令を実行したところか、プリミティブが
+    /// `SchemeError::yield_to_host`で中断を求めたところで評価を中断する。
+    /// 中断した評価は`resume`で再開できるので、1つのスレッドで複数の評価を交互に進められる。
+    pub fn start(&mut self, name: &str, input: &str, steps: u64) -> Result<Execution, Error> {
+        let suspended = Suspended {
+            owner: self.id,
+            machine: Machine::spawn(Rc::new(Code::from(Vec::new()))),
+            rest: read(name, input)?.into_iter(),
+            budget: Budget::new(self.limits),
+            yielded: None,
+        };
+        self.run_for(suspended, steps)
+    }
+
+    /// 中断した評価をさらに`steps`個の命令まで進める。プリミティブが中断を求めていた場合は
+    /// `value`がその戻り値になり、命令の数で中断した場合は`value`は使わない。
+    /// 他のインタプリタで中断した評価はエラーになる。
+    pub fn resume(&mut self, mut suspended: Suspended, value: Value, steps: u64) -> Result<Execution, Error> {
+        // コンパイル済みのコードは、グローバル変数を中断したインタプリタのスロット番号で参照している。
+        if suspended.owner != self.id {
+            return Err(Error::Scheme(SchemeError::runtime("cannot resume an evaluation suspended by another interpreter")));
+        }
+        if suspended.yielded.take().is_some() {
+            suspended.machine.push_result(value);
+        }
+        self.run_for(suspended, steps)
+    }
+
+    // 中断した評価の資源の使用量は、評価ごとに別々に数える。
+    fn run_for(&mut self, mut suspended: Suspended, mut steps: u64) -> Result<Execution, Error> {
+        self.global.replace_budget(mem::take(&mut suspended.budget));
+        loop {
+            match suspended.machine.resume(&mut self.global, &mut steps)? {
+                Status::Done(value) => {
+                    // 前の式の定義がコンパイルに影響するので、残りの式は1つずつコンパイルする。
+                    match suspended.rest.next() {
+                        Some(exp) => suspended.machine = Machine::spawn(exp.compile(&mut self.global)?),
+                        None => return Ok(Execution::Done(value)),
+                    }
+                }
+                Status::Paused => break,
+                Status::Yielded(value) => {
+                    suspended.yielded = Some(value);
+                    break;
+                }
+            }
+        }
+        suspended.budget = self.global.replace_budget(Budget::default());
+        Ok(Execution::Suspended(suspended))
+    }
+
+    pub fn eval_file<P: AsRef<Path>>(&mut self, path: P) -> Result<Value, Error> {
+        let mut file = File::open(path.as_ref())?;
+        let mut buf = String::new();
+        file.read_to_string(&mut buf)?;
+        self.eval_source(&path.as_ref().display().to_string(), &buf)
+    }
+
+    /// グローバル変数`name`に束縛された手続きを`args`に適用する。
+    pub fn call(&mut self, name: &str, args: Vec<Value>) -> Result<Value, Error> {
+        let f = self.lookup(name)?;
+        self.global.replace_budget(Budget::new(self.limits));
+        Ok(Machine::apply(f, args, &mut self.global)?)
+    }
+
+    /// 以後の`eval_str`や`call`などの1回の呼び出しで使える資源の上限を設定する。
+    /// `start`で始めた評価では、中断と再開をまたいで数える。
+    /// 上限に達すると`ErrorKind::LimitExceeded`のエラーになる。
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    pub fn lookup(&self, name: &str) -> Result<Value, Error> {
+        self.global
+            .get(name)
+            .cloned()
+            .ok_or_else(|| Error::Scheme(SchemeError::unbound_variable(name)))
+    }
+
+    /// グローバル環境に束縛されている名前の一覧。
+    pub fn names(&self) -> Vec<String> {
+        self.global.names().map(|name| name.to_owned()).collect()
+    }
+
+    pub fn define(&mut self, name: &str, value: Value) {
+        self.global.insert(name, value);
+    }
+
+    /// 到達できなくなった循環した構造を回収し、回収したオブジェクトの数を返す。
+    /// 回収は評価中にも自動で行われるので、通常は呼ぶ必要はない。
+    pub fn collect_garbage(&mut self) -> usize {
+        self.global.heap().collect()
+    }
+
+    /// Rustのクロージャを手続き`name`として登録する。引数の数は呼び出し時に`arity`で確かめる。
+    pub fn register<F>(&mut self, name: &str, arity: Arity, f: F)
+        where F: Fn(Vec<Value>) -> Result<Value, String> + 'static
+    {
+        self.define(name,
+                    Value::native(name, arity, move |args| f(args).map_err(SchemeError::from)));
+    }
+
+    /// 型付きのRustの関数を手続き`name`として登録する。引数の数と型は呼び出し時に確かめる。
+    pub fn register_fn<F, Args>(&mut self, name: &str, f: F)
+        where F: IntoProcedure<Args>
+    {
+        self.define(name, f.into_procedure(name));
+    }
+}
+
+fn read(name: &str, input: &str) -> Result<Vec<Ast>, Error> {
+    let source = Source::new(name, input);
+    read_source(&source).map_err(|e| {
+        let span = Span {
+            source: source.clone(),
+            line: e.position.line as usize,
+            column: e.position.column as usize,
+        };
+        let mut message = span.to_string();
+        for error in &e.errors {
+            message.push_str(&format!("\n  {}", error));
+        }
+        if let Some((line, caret)) = span.snippet() {
+            message.push_str(&format!("\n    {}\n    {}", line, caret));
+        }
+        Error::Read(message)
+    })
+}
@@ -0,0 +1,27 @@
+extern crate combine;
+extern crate num;
+
+mod compiler;
+mod convert;
+mod error;
+mod gc;
+mod interpreter;
+mod limits;
+mod native;
+mod number;
+mod primitive;
+mod reader;
+mod span;
+mod syntax;
+mod value;
+mod vm;
+
+pub use convert::{FromValue, IntoProcedure, IntoResult, IntoValue};
+pub use error::{ErrorKind, SchemeError};
+pub use interpreter::{Error, Execution, Interpreter, Suspended};
+pub use limits::{Limit, Limits};
+pub use native::{Arity, Native};
+pub use number::Number;
+pub use reader::is_incomplete;
+pub use span::{Source, Span};
+pub use value::Value;
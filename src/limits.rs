@@ -0,0 +1,115 @@
+use std::time::{Duration, Instant};
+use error::SchemeError;
+use gc::Heap;
+
+/// 1回の評価に使える資源の上限。Noneの項目は制限しない。
+/// `Interpreter::set_limits`で設定し、`eval_str`や`call`などの呼び出しごとに数え直す。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// 実行できるVMの命令の数。マクロの展開で実行される命令と、コンパイルやマクロの展開で
+    /// 処理した式の数も含む。
+    pub steps: Option<u64>,
+    /// 評価に費やした時間。`start`で始めた評価では、中断している間の時間は含まない。
+    /// 命令の合間に確かめるので、時間のかかるプリミティブの途中では止まらない。
+    pub time: Option<Duration>,
+    /// dumpの深さ。末尾位置でない手続きの呼び出しやifの入れ子で深くなる。
+    pub dump_depth: Option<usize>,
+    /// 生きているペア、ベクタ、クロージャや継続に捕捉されたフレーム、継続、文字列、
+    /// バイトベクタの大きさの合計。ペアと継続は1、ベクタとフレームは1と要素の数の和、
+    /// 文字列とバイトベクタは1と8バイトごとに1の和として数える。他のインタプリタのものは
+    /// 含まない。`make-vector`などは作る前に確かめ、多倍長整数は計算する前に結果の大きさが
+    /// 残りに収まるかを確かめる。
+    pub heap: Option<usize>,
+}
+
+/// 上限に達した資源。`ErrorKind::LimitExceeded`として返る。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Steps,
+    Time,
+    DumpDepth,
+    Heap,
+}
+
+// 時刻の取得は命令の実行に比べて重いので、この数の命令ごとに確かめる。コンパイルやマクロの
+// 展開は1回ごとに確かめる。
+const CLOCK_INTERVAL: u64 = 1024;
+
+// 評価1回分の資源の使用量。
+#[derive(Debug, Clone)]
+pub struct Budget {
+    limits: Limits,
+    // 上限が1つもなければ、命令ごとに数える必要がない。
+    unlimited: bool,
+    steps: u64,
+    // 中断するまでに費やした時間と、最後に再開した時刻。
+    elapsed: Duration,
+    resumed: Option<Instant>,
+}
+
+impl Default for Budget {
+    fn default() -> Budget {
+        Budget::new(Limits::default())
+    }
+}
+
+impl Budget {
+    pub fn new(limits: Limits) -> Budget {
+        Budget {
+            limits,
+            unlimited: limits == Limits::default(),
+            steps: 0,
+            elapsed: Duration::ZERO,
+            resumed: None,
+        }
+    }
+
+    pub fn heap_limit(&self) -> Option<usize> {
+        self.limits.heap
+    }
+
+    // 実行を始めるときや再開するときに呼び、時間を数え始める。
+    pub fn resume(&mut self) {
+        self.resumed = Some(Instant::now());
+    }
+
+    // 中断するときに呼び、それまでに費やした時間を足しておく。
+    pub fn pause(&mut self) {
+        if let Some(resumed) = self.resumed.take() {
+            self.elapsed += resumed.elapsed();
+        }
+    }
+
+    fn is_out_of_time(&self) -> bool {
+        let elapsed = self.elapsed + self.resumed.map_or(Duration::ZERO, |resumed| resumed.elapsed());
+        self.limits.time.is_some_and(|time| elapsed >= time)
+    }
+
+    // 命令を1つ実行するたびに呼ぶ。dump_depthはその時点のdumpの深さ。
+    pub fn spend(&mut self, dump_depth: usize, heap: &Heap) -> Result<(), SchemeError> {
+        let check_clock = (self.steps + 1).is_multiple_of(CLOCK_INTERVAL);
+        self.charge(dump_depth, heap, check_clock)
+    }
+
+    // コンパイルやマクロの展開で式を1つ処理するたびに呼ぶ。命令より重いので毎回時刻も確かめる。
+    pub fn spend_expansion(&mut self, heap: &Heap) -> Result<(), SchemeError> {
+        self.charge(0, heap, true)
+    }
+
+    fn charge(&mut self, dump_depth: usize, heap: &Heap, check_clock: bool) -> Result<(), SchemeError> {
+        if self.unlimited {
+            return Ok(());
+        }
+        self.steps += 1;
+        if self.limits.steps.is_some_and(|max| self.steps > max) {
+            return Err(SchemeError::limit_exceeded(Limit::Steps));
+        }
+        if check_clock && self.is_out_of_time() {
+            return Err(SchemeError::limit_exceeded(Limit::Time));
+        }
+        if self.limits.dump_depth.is_some_and(|max| dump_depth > max) {
+            return Err(SchemeError::limit_exceeded(Limit::DumpDepth));
+        }
+        heap.reserve(0)
+    }
+}
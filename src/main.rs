@@ -1,35 +1,27 @@
-extern crate combine;
+extern crate rustyline;
+extern crate secd;
 
-mod compiler;
-mod primitive;
-mod reader;
-mod value;
-mod vm;
+mod repl;
 
 use std::env::args;
-use std::fs::File;
-use std::io::Read;
-use primitive::define_primitives;
-use reader::read;
-use vm::Machine;
+use std::process::exit;
+use secd::Interpreter;
 
 fn main() {
-    let mut lib = File::open("./lib/base.scm").unwrap();
-    let mut buf = String::new();
-    lib.read_to_string(&mut buf).unwrap();
-    let (ast, _) = read(&buf).unwrap();
-    let mut global = define_primitives();
-    for exp in ast {
-        let code = exp.compile(&global).unwrap();
-        Machine::run(Vec::new(), code, &mut global).unwrap();
-    }
-
-    let mut file = File::open(args().nth(1).unwrap()).unwrap();
-    let mut buf = String::new();
-    file.read_to_string(&mut buf).unwrap();
-    let (ast, _) = read(&buf).unwrap();
-    for exp in ast {
-        let code = exp.compile(&global).unwrap();
-        Machine::run(Vec::new(), code, &mut global).unwrap();
+    let mut interpreter = match Interpreter::new() {
+        Ok(interpreter) => interpreter,
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1);
+        }
+    };
+    // ファイルが指定されなければREPLを起動する。
+    let result = match args().nth(1) {
+        Some(path) => interpreter.eval_file(&path).map(|_| ()).map_err(|e| e.to_string()),
+        None => repl::run(interpreter).map_err(|e| e.to_string()),
+    };
+    if let Err(message) = result {
+        eprintln!("{}", message);
+        exit(1);
     }
 }
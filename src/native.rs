@@ -0,0 +1,81 @@
+use std::fmt;
+use error::SchemeError;
+use value::Value;
+
+/// ネイティブ手続きが受け付ける引数の数。
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arity {
+    Exactly(usize),
+    AtLeast(usize),
+    Between(usize, usize),
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Arity::Exactly(1) => write!(f, "1 argument"),
+            Arity::Exactly(k) => write!(f, "{} arguments", k),
+            Arity::AtLeast(k) => write!(f, "at least {} argument{}", k, if k == 1 { "" } else { "s" }),
+            Arity::Between(min, max) => write!(f, "{} to {} arguments", min, max),
+        }
+    }
+}
+
+impl Arity {
+    pub fn accepts(&self, n: usize) -> bool {
+        match *self {
+            Arity::Exactly(k) => n == k,
+            Arity::AtLeast(k) => n >= k,
+            Arity::Between(min, max) => min <= n && n <= max,
+        }
+    }
+}
+
+/// Rustの側から実行時に登録される手続き。`Value::Primitive`の関数ポインタと異なり、
+/// データベースへのハンドルやカウンタのような状態を持つクロージャを使える。
+pub struct Native {
+    name: String,
+    arity: Arity,
+    f: Box<dyn Fn(Vec<Value>) -> Result<Value, SchemeError>>,
+}
+
+impl Native {
+    pub fn new<F>(name: &str, arity: Arity, f: F) -> Native
+        where F: Fn(Vec<Value>) -> Result<Value, SchemeError> + 'static
+    {
+        Native {
+            name: name.to_owned(),
+            arity,
+            f: Box::new(f),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn arity(&self) -> Arity {
+        self.arity
+    }
+
+    /// 引数の数を確かめてから呼び出す。
+    pub fn call(&self, args: Vec<Value>) -> Result<Value, SchemeError> {
+        if !self.arity.accepts(args.len()) {
+            return Err(SchemeError::arity_expected(&self.name, self.arity, args.len()));
+        }
+        (self.f)(args)
+    }
+}
+
+impl fmt::Debug for Native {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Native({})", self.name)
+    }
+}
+
+// クロージャ同士は比較できないので、同じオブジェクトかどうかで比べる。
+impl PartialEq for Native {
+    fn eq(&self, other: &Native) -> bool {
+        ::std::ptr::eq(self, other)
+    }
+}
@@ -0,0 +1,521 @@
+use std::cmp::Ordering;
+use std::fmt;
+use num::{BigInt, BigRational, Integer as NumInteger, One, Signed, ToPrimitive, Zero};
+
+// 正確な整数はi64に収まる限りInteger、収まらなければBigで表す。
+// Rationalは分母が1でない正確な有理数、Realは不正確な実数。
+// 演算結果は常にこの規則で正規化するので、派生したPartialEqでeqv?の比較ができる。
+#[derive(Debug, Clone, PartialEq)]
+pub enum Number {
+    Integer(i64),
+    Big(Box<BigInt>),
+    Rational(Box<BigRational>),
+    Real(f64),
+}
+
+// exptの正確な結果として許容するビット数。これを超える計算はエラーにする。
+const MAX_EXPT_BITS: u64 = 1 << 24;
+
+// #e1e10のような正確な10進数で許容する指数。読み込みは資源の上限の外で行われるので、
+// 10の累乗の計算に時間がかからないよう、exptの上限よりずっと小さくする。
+const MAX_EXACT_EXPONENT: usize = 10_000;
+
+impl From<i64> for Number {
+    fn from(i: i64) -> Number {
+        Number::Integer(i)
+    }
+}
+
+impl Number {
+    pub fn big(i: BigInt) -> Number {
+        match i.to_i64() {
+            Some(i) => Number::Integer(i),
+            None => Number::Big(Box::new(i)),
+        }
+    }
+
+    pub fn rational(r: BigRational) -> Number {
+        if r.is_integer() {
+            Number::big(r.to_integer())
+        } else {
+            Number::Rational(Box::new(r))
+        }
+    }
+
+    pub fn is_exact(&self) -> bool {
+        !matches!(*self, Number::Real(_))
+    }
+
+    pub fn is_integer(&self) -> bool {
+        match *self {
+            Number::Integer(_) | Number::Big(_) => true,
+            Number::Rational(_) => false,
+            Number::Real(f) => f.is_finite() && f.fract() == 0.0,
+        }
+    }
+
+    pub fn is_exact_integer(&self) -> bool {
+        matches!(*self, Number::Integer(_) | Number::Big(_))
+    }
+
+    // 正確な数の絶対値を表すのに必要なビット数。分数は分子と分母の和。不正確な数は0。
+    pub fn bits(&self) -> u64 {
+        match *self {
+            Number::Integer(i) => u64::from(64 - i.unsigned_abs().leading_zeros()),
+            Number::Big(ref i) => i.bits(),
+            Number::Rational(ref r) => r.numer().bits() + r.denom().bits(),
+            Number::Real(_) => 0,
+        }
+    }
+
+    pub fn is_nan(&self) -> bool {
+        match *self {
+            Number::Real(f) => f.is_nan(),
+            _ => false,
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        match *self {
+            Number::Integer(i) => i as f64,
+            Number::Big(ref b) => b.to_f64().unwrap_or(f64::NAN),
+            Number::Rational(ref r) => r.to_f64().unwrap_or(f64::NAN),
+            Number::Real(f) => f,
+        }
+    }
+
+    fn to_bigint(&self) -> BigInt {
+        match *self {
+            Number::Integer(i) => BigInt::from(i),
+            Number::Big(ref b) => (**b).to_owned(),
+            Number::Rational(ref r) => r.to_integer(),
+            Number::Real(f) => BigRational::from_float(f).map(|r| r.to_integer()).unwrap_or_default(),
+        }
+    }
+
+    fn to_rational(&self) -> BigRational {
+        match *self {
+            Number::Integer(i) => BigRational::from_integer(BigInt::from(i)),
+            Number::Big(ref b) => BigRational::from_integer((**b).to_owned()),
+            Number::Rational(ref r) => (**r).to_owned(),
+            Number::Real(f) => BigRational::from_float(f).unwrap_or_default(),
+        }
+    }
+
+    pub fn to_exact(&self) -> Result<Number, String> {
+        match *self {
+            Number::Real(f) => {
+                BigRational::from_float(f)
+                    .map(Number::rational)
+                    .ok_or_else(|| format!("no exact representation: {}", self))
+            }
+            ref n => Ok(n.to_owned()),
+        }
+    }
+
+    pub fn to_inexact(&self) -> Number {
+        Number::Real(self.to_f64())
+    }
+
+    // 正確な数同士なら正確に、どちらかが不正確なら不正確に計算する。
+    fn arith<F, G, H, R>(&self, other: &Number, fixnum: F, big: G, rational: H, real: R) -> Number
+        where F: Fn(i64, i64) -> Option<i64>,
+              G: Fn(BigInt, BigInt) -> BigInt,
+              H: Fn(BigRational, BigRational) -> BigRational,
+              R: Fn(f64, f64) -> f64
+    {
+        match (self, other) {
+            (&Number::Integer(x), &Number::Integer(y)) => {
+                match fixnum(x, y) {
+                    Some(i) => Number::Integer(i),
+                    None => Number::big(big(BigInt::from(x), BigInt::from(y))),
+                }
+            }
+            (&Number::Real(_), _) |
+            (_, &Number::Real(_)) => Number::Real(real(self.to_f64(), other.to_f64())),
+            (&Number::Rational(_), _) |
+            (_, &Number::Rational(_)) => {
+                Number::rational(rational(self.to_rational(), other.to_rational()))
+            }
+            _ => Number::big(big(self.to_bigint(), other.to_bigint())),
+        }
+    }
+
+    pub fn add(&self, other: &Number) -> Number {
+        self.arith(other, i64::checked_add, |x, y| x + y, |x, y| x + y, |x, y| x + y)
+    }
+
+    pub fn sub(&self, other: &Number) -> Number {
+        self.arith(other, i64::checked_sub, |x, y| x - y, |x, y| x - y, |x, y| x - y)
+    }
+
+    pub fn mul(&self, other: &Number) -> Number {
+        self.arith(other, i64::checked_mul, |x, y| x * y, |x, y| x * y, |x, y| x * y)
+    }
+
+    pub fn div(&self, other: &Number) -> Result<Number, String> {
+        match (self, other) {
+            (&Number::Real(_), _) |
+            (_, &Number::Real(_)) => Ok(Number::Real(self.to_f64() / other.to_f64())),
+            _ if other.is_zero() => Err("division by zero: /".to_owned()),
+            _ => Ok(Number::rational(self.to_rational() / other.to_rational())),
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        match *self {
+            Number::Integer(i) => i == 0,
+            Number::Big(ref b) => b.is_zero(),
+            Number::Rational(ref r) => r.is_zero(),
+            Number::Real(f) => f == 0.0,
+        }
+    }
+
+    pub fn signum(&self) -> Ordering {
+        self.compare(&Number::Integer(0)).unwrap_or(Ordering::Equal)
+    }
+
+    pub fn neg(&self) -> Number {
+        match *self {
+            // 0.0の符号を反転させて-0.0にするため、0から引かない。
+            Number::Real(x) => Number::Real(-x),
+            _ => Number::Integer(0).sub(self),
+        }
+    }
+
+    pub fn abs(&self) -> Number {
+        if self.signum() == Ordering::Less {
+            self.neg()
+        } else {
+            self.to_owned()
+        }
+    }
+
+    // NaNとの比較はNoneになる。
+    pub fn compare(&self, other: &Number) -> Option<Ordering> {
+        match (self, other) {
+            (&Number::Integer(x), &Number::Integer(y)) => Some(x.cmp(&y)),
+            (&Number::Real(_), _) |
+            (_, &Number::Real(_)) => self.to_f64().partial_cmp(&other.to_f64()),
+            _ => Some(self.to_rational().cmp(&other.to_rational())),
+        }
+    }
+
+    // quotient、remainder、moduloの共通部分。引数はどちらも整数でなければならない。
+    fn integer_division<F, G>(&self, other: &Number, name: &str, exact: F, inexact: G) -> Result<Number, String>
+        where F: Fn(&BigInt, &BigInt) -> BigInt,
+              G: Fn(f64, f64) -> f64
+    {
+        if !self.is_integer() || !other.is_integer() {
+            return Err(format!("integer required: {}", name));
+        }
+        if other.is_zero() {
+            return Err(format!("division by zero: {}", name));
+        }
+        if self.is_exact() && other.is_exact() {
+            Ok(Number::big(exact(&self.to_bigint(), &other.to_bigint())))
+        } else {
+            Ok(Number::Real(inexact(self.to_f64(), other.to_f64())))
+        }
+    }
+
+    pub fn quotient(&self, other: &Number) -> Result<Number, String> {
+        self.integer_division(other, "quotient", |x, y| x / y, |x, y| (x / y).trunc())
+    }
+
+    pub fn remainder(&self, other: &Number) -> Result<Number, String> {
+        self.integer_division(other, "remainder", |x, y| x % y, |x, y| x % y)
+    }
+
+    pub fn modulo(&self, other: &Number) -> Result<Number, String> {
+        self.integer_division(other,
+                              "modulo",
+                              |x, y| x.mod_floor(y),
+                              |x, y| x - y * (x / y).floor())
+    }
+
+    pub fn gcd(&self, other: &Number) -> Result<Number, String> {
+        self.integer_division_free(other, "gcd", |x, y| x.gcd(y))
+    }
+
+    pub fn lcm(&self, other: &Number) -> Result<Number, String> {
+        self.integer_division_free(other, "lcm", |x, y| x.lcm(y))
+    }
+
+    fn integer_division_free<F>(&self, other: &Number, name: &str, f: F) -> Result<Number, String>
+        where F: Fn(&BigInt, &BigInt) -> BigInt
+    {
+        if !self.is_integer() || !other.is_integer() {
+            return Err(format!("integer required: {}", name));
+        }
+        let result = Number::big(f(&self.to_bigint(), &other.to_bigint()));
+        if self.is_exact() && other.is_exact() {
+            Ok(result)
+        } else {
+            Ok(result.to_inexact())
+        }
+    }
+
+    pub fn numerator(&self) -> Number {
+        match *self {
+            Number::Rational(ref r) => Number::big(r.numer().to_owned()),
+            Number::Real(_) => {
+                match self.to_exact() {
+                    Ok(n) => n.numerator().to_inexact(),
+                    Err(_) => self.to_owned(),
+                }
+            }
+            ref n => n.to_owned(),
+        }
+    }
+
+    pub fn denominator(&self) -> Number {
+        match *self {
+            Number::Rational(ref r) => Number::big(r.denom().to_owned()),
+            Number::Real(_) => {
+                match self.to_exact() {
+                    Ok(n) => n.denominator().to_inexact(),
+                    Err(_) => Number::Real(1.0),
+                }
+            }
+            _ => Number::Integer(1),
+        }
+    }
+
+    pub fn floor(&self) -> Number {
+        match *self {
+            Number::Rational(ref r) => Number::big(r.floor().to_integer()),
+            Number::Real(f) => Number::Real(f.floor()),
+            ref n => n.to_owned(),
+        }
+    }
+
+    pub fn ceiling(&self) -> Number {
+        match *self {
+            Number::Rational(ref r) => Number::big(r.ceil().to_integer()),
+            Number::Real(f) => Number::Real(f.ceil()),
+            ref n => n.to_owned(),
+        }
+    }
+
+    pub fn truncate(&self) -> Number {
+        match *self {
+            Number::Rational(ref r) => Number::big(r.trunc().to_integer()),
+            Number::Real(f) => Number::Real(f.trunc()),
+            ref n => n.to_owned(),
+        }
+    }
+
+    // R7RSのroundはちょうど中間の値を偶数の側に丸める。
+    pub fn round(&self) -> Number {
+        match *self {
+            Number::Rational(ref r) => {
+                let floor = r.floor();
+                let diff = &**r - &floor;
+                let half = BigRational::new(BigInt::one(), BigInt::from(2));
+                let floor = floor.to_integer();
+                let rounded = match diff.cmp(&half) {
+                    Ordering::Less => floor,
+                    Ordering::Greater => floor + 1,
+                    Ordering::Equal if floor.is_even() => floor,
+                    Ordering::Equal => floor + 1,
+                };
+                Number::big(rounded)
+            }
+            Number::Real(f) => {
+                let floor = f.floor();
+                let diff = f - floor;
+                let rounded = if diff < 0.5 {
+                    floor
+                } else if diff > 0.5 || floor % 2.0 != 0.0 {
+                    floor + 1.0
+                } else {
+                    floor
+                };
+                Number::Real(rounded)
+            }
+            ref n => n.to_owned(),
+        }
+    }
+
+    // 正確な数の平方根は、結果も正確に表せる場合は正確な数を返す。
+    pub fn sqrt(&self) -> Result<Number, String> {
+        if self.signum() == Ordering::Less {
+            return Err("complex numbers are not supported: sqrt".to_owned());
+        }
+        if self.is_exact() {
+            let r = self.to_rational();
+            let (numer, denom) = (r.numer().sqrt(), r.denom().sqrt());
+            if &numer * &numer == *r.numer() && &denom * &denom == *r.denom() {
+                return Ok(Number::rational(BigRational::new(numer, denom)));
+            }
+        }
+        Ok(Number::Real(self.to_f64().sqrt()))
+    }
+
+    pub fn expt(&self, other: &Number) -> Result<Number, String> {
+        if self.is_exact() && other.is_exact_integer() {
+            let e = other.to_bigint();
+            let n = e.abs().to_u32().ok_or_else(|| "exponent too large: expt".to_owned())?;
+            let base = self.to_rational();
+            if base.is_zero() && e.is_negative() {
+                return Err("division by zero: expt".to_owned());
+            }
+            let bits = base.numer().bits().max(base.denom().bits());
+            if bits > 1 && bits.saturating_mul(u64::from(n)) > MAX_EXPT_BITS {
+                return Err("result too large: expt".to_owned());
+            }
+            let numer = num::pow(base.numer().to_owned(), n as usize);
+            let denom = num::pow(base.denom().to_owned(), n as usize);
+            let result = if e.is_negative() {
+                BigRational::new(denom, numer)
+            } else {
+                BigRational::new(numer, denom)
+            };
+            Ok(Number::rational(result))
+        } else if other.is_exact_integer() {
+            match other.to_bigint().to_i32() {
+                Some(i) => Ok(Number::Real(self.to_f64().powi(i))),
+                None => Ok(Number::Real(self.to_f64().powf(other.to_f64()))),
+            }
+        } else {
+            Ok(Number::Real(self.to_f64().powf(other.to_f64())))
+        }
+    }
+
+    pub fn to_string_radix(&self, radix: u32) -> Result<String, String> {
+        match *self {
+            Number::Integer(i) => Ok(BigInt::from(i).to_str_radix(radix)),
+            Number::Big(ref b) => Ok(b.to_str_radix(radix)),
+            Number::Rational(ref r) => {
+                Ok(format!("{}/{}",
+                           r.numer().to_str_radix(radix),
+                           r.denom().to_str_radix(radix)))
+            }
+            Number::Real(_) if radix == 10 => Ok(format!("{}", self)),
+            Number::Real(_) => Err("inexact numbers can only be written in radix 10".to_owned()),
+        }
+    }
+
+    // R7RSの数値の構文を読む。#x、#b、#o、#dによる基数と#e、#iによる正確性の接頭辞を扱う。
+    // 数として読めなければNoneを返す。
+    pub fn parse(s: &str, radix: u32) -> Option<Number> {
+        let mut radix = radix;
+        let mut exactness = None;
+        let mut rest = s;
+        while rest.starts_with('#') {
+            let mut chars = rest[1..].chars();
+            match chars.next().map(|c| c.to_ascii_lowercase()) {
+                Some('x') => radix = 16,
+                Some('b') => radix = 2,
+                Some('o') => radix = 8,
+                Some('d') => radix = 10,
+                Some('e') => exactness = Some(true),
+                Some('i') => exactness = Some(false),
+                _ => return None,
+            }
+            rest = chars.as_str();
+        }
+        let n = parse_real(rest, radix, exactness == Some(true))?;
+        match exactness {
+            Some(true) => n.to_exact().ok(),
+            Some(false) => Some(n.to_inexact()),
+            None => Some(n),
+        }
+    }
+}
+
+fn parse_real(s: &str, radix: u32, exact: bool) -> Option<Number> {
+    match s {
+        "+inf.0" => return Some(Number::Real(f64::INFINITY)),
+        "-inf.0" => return Some(Number::Real(f64::NEG_INFINITY)),
+        "+nan.0" | "-nan.0" => return Some(Number::Real(f64::NAN)),
+        _ => (),
+    }
+    let (negative, body) = if let Some(body) = s.strip_prefix('-') {
+        (true, body)
+    } else {
+        (false, s.strip_prefix('+').unwrap_or(s))
+    };
+    let n = if let Some(slash) = body.find('/') {
+        let numer = parse_digits(&body[..slash], radix)?;
+        let denom = parse_digits(&body[slash + 1..], radix)?;
+        if denom.is_zero() {
+            return None;
+        }
+        Number::rational(BigRational::new(numer, denom))
+    } else if let Some(i) = parse_digits(body, radix) {
+        Number::big(i)
+    } else if radix == 10 {
+        parse_decimal(body, exact)?
+    } else {
+        return None;
+    };
+    Some(if negative { n.neg() } else { n })
+}
+
+fn parse_digits(s: &str, radix: u32) -> Option<BigInt> {
+    if s.is_empty() || !s.chars().all(|c| c.is_digit(radix)) {
+        return None;
+    }
+    BigInt::parse_bytes(s.as_bytes(), radix)
+}
+
+// 1.5、.5、1e10、1.5e-3のような10進数。exactが真なら正確な有理数として読む。
+fn parse_decimal(s: &str, exact: bool) -> Option<Number> {
+    let (mantissa, exponent) = match s.find(['e', 'E']) {
+        Some(i) => (&s[..i], Some(&s[i + 1..])),
+        None => (s, None),
+    };
+    let (int_part, frac_part) = match mantissa.find('.') {
+        Some(i) => (&mantissa[..i], &mantissa[i + 1..]),
+        None => (mantissa, ""),
+    };
+    let digits = format!("{}{}", int_part, frac_part);
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let exponent = match exponent {
+        Some(e) => {
+            let digits = e.strip_prefix('-').or_else(|| e.strip_prefix('+')).unwrap_or(e);
+            if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+                return None;
+            }
+            e.parse::<i64>().ok()?
+        }
+        None => 0,
+    };
+    if exact {
+        let scale = exponent.checked_sub(frac_part.len() as i64)?;
+        let ten = BigInt::from(10);
+        let n = scale.abs().to_usize()?;
+        if n > MAX_EXACT_EXPONENT {
+            return None;
+        }
+        let mantissa = BigRational::from_integer(BigInt::parse_bytes(digits.as_bytes(), 10)?);
+        let factor = BigRational::from_integer(num::pow(ten, n));
+        Some(Number::rational(if scale < 0 {
+                                  mantissa / factor
+                              } else {
+                                  mantissa * factor
+                              }))
+    } else {
+        s.parse::<f64>().ok().map(Number::Real)
+    }
+}
+
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Number::Integer(i) => write!(f, "{}", i),
+            Number::Big(ref b) => write!(f, "{}", b),
+            Number::Rational(ref r) => write!(f, "{}/{}", r.numer(), r.denom()),
+            Number::Real(x) if x.is_nan() => write!(f, "+nan.0"),
+            Number::Real(x) if x.is_infinite() => {
+                write!(f, "{}", if x > 0.0 { "+inf.0" } else { "-inf.0" })
+            }
+            // Debugの表記は1.0のように常に小数点か指数を含むので、不正確な数として読み戻せる。
+            Number::Real(x) => write!(f, "{:?}", x),
+        }
+    }
+}
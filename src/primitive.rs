@@ -1,258 +1,1034 @@
-use std::collections::HashMap;
-use value::{Value, vec2cons};
-use vm::Global;
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::rc::Rc;
+use value::{CycleCheck, Pair, Value, Vector, vec2cons};
+use vm::{Global, Special};
+use number::Number;
+use convert::IntoProcedure;
+use error::SchemeError;
+use gc;
 
 pub fn define_primitives() -> Global {
-    let mut g = HashMap::new();
-    g.insert("print".to_owned(), Value::Primitive(print));
-    g.insert("undefined".to_owned(), Value::Primitive(undefined));
-    g.insert("cons".to_owned(), Value::Primitive(cons));
-    g.insert("car".to_owned(), Value::Primitive(car));
-    g.insert("cdr".to_owned(), Value::Primitive(cdr));
-    g.insert("eq?".to_owned(), Value::Primitive(eq_p));
-    g.insert("pair?".to_owned(), Value::Primitive(pair_p));
-    g.insert("not".to_owned(), Value::Primitive(not));
-    g.insert("null?".to_owned(), Value::Primitive(null_p));
-    g.insert("list".to_owned(), Value::Primitive(list));
-    g.insert("+".to_owned(), Value::Primitive(add));
-    g.insert("-".to_owned(), Value::Primitive(sub));
-    g.insert("*".to_owned(), Value::Primitive(mul));
-    g.insert("=".to_owned(), Value::Primitive(eq));
-    g.insert(">".to_owned(), Value::Primitive(gt));
-    g.insert(">=".to_owned(), Value::Primitive(ge));
-    g.insert("<".to_owned(), Value::Primitive(lt));
-    g.insert("<=".to_owned(), Value::Primitive(le));
+    let mut g = Global::new();
+    g.insert("print", Value::Primitive(print));
+    define(&mut g, "undefined", || Value::Undefined);
+    define(&mut g, "cons", |car: Value, cdr: Value| Value::cons(car, cdr));
+    define(&mut g, "car", |pair: Rc<Pair>| pair.car());
+    define(&mut g, "cdr", |pair: Rc<Pair>| pair.cdr());
+    g.insert("set-car!", Value::Primitive(set_car));
+    g.insert("set-cdr!", Value::Primitive(set_cdr));
+    g.insert("list-set!", Value::Primitive(list_set));
+    g.insert("append!", Value::Primitive(append_bang));
+    g.insert("reverse!", Value::Primitive(reverse_bang));
+    define(&mut g, "eq?", |x: Value, y: Value| x.is_eq(&y));
+    define(&mut g, "eqv?", |x: Value, y: Value| x.is_eqv(&y));
+    define(&mut g, "equal?", |x: Value, y: Value| x.is_equal(&y));
+    g.insert("memq", Value::Primitive(memq));
+    g.insert("memv", Value::Primitive(memv));
+    g.insert("member", Value::Primitive(member));
+    g.insert("assq", Value::Primitive(assq));
+    g.insert("assv", Value::Primitive(assv));
+    g.insert("assoc", Value::Primitive(assoc));
+    define(&mut g, "pair?", |v: Value| matches!(v, Value::Cell(_)));
+    define(&mut g, "not", |v: Value| matches!(v, Value::Boolean(false)));
+    define(&mut g, "null?", |v: Value| matches!(v, Value::Nil));
+    g.insert("list", Value::Primitive(list));
+    g.insert("+", Value::Primitive(add));
+    g.insert("-", Value::Primitive(sub));
+    g.insert("*", Value::Primitive(mul));
+    g.insert("/", Value::Primitive(div));
+    g.insert("=", Value::Primitive(eq));
+    g.insert(">", Value::Primitive(gt));
+    g.insert(">=", Value::Primitive(ge));
+    g.insert("<", Value::Primitive(lt));
+    g.insert("<=", Value::Primitive(le));
+    define(&mut g, "number?", |v: Value| matches!(v, Value::Number(_)));
+    define(&mut g, "complex?", |v: Value| matches!(v, Value::Number(_)));
+    define(&mut g, "real?", |v: Value| matches!(v, Value::Number(_)));
+    // 複素数はないので、有限な数はすべて有理数として扱う。
+    define(&mut g, "rational?", |v: Value| {
+        match v {
+            Value::Number(ref n) => n.to_f64().is_finite() || n.is_exact(),
+            _ => false,
+        }
+    });
+    define(&mut g, "integer?", |v: Value| {
+        match v {
+            Value::Number(ref n) => n.is_integer(),
+            _ => false,
+        }
+    });
+    define(&mut g, "exact?", |n: Number| n.is_exact());
+    define(&mut g, "inexact?", |n: Number| !n.is_exact());
+    define(&mut g, "exact-integer?", |v: Value| {
+        match v {
+            Value::Number(ref n) => n.is_exact_integer(),
+            _ => false,
+        }
+    });
+    define(&mut g, "nan?", |n: Number| n.is_nan());
+    define(&mut g, "zero?", |n: Number| n.is_zero());
+    define(&mut g, "positive?", |n: Number| n.signum() == Ordering::Greater);
+    define(&mut g, "negative?", |n: Number| n.signum() == Ordering::Less);
+    define(&mut g, "odd?", |n: Number| n.modulo(&Number::from(2)).map(|r| !r.is_zero()));
+    define(&mut g, "even?", |n: Number| n.modulo(&Number::from(2)).map(|r| r.is_zero()));
+    g.insert("max", Value::Primitive(max));
+    g.insert("min", Value::Primitive(min));
+    define(&mut g, "abs", |n: Number| n.abs());
+    g.insert("quotient", Value::Primitive(quotient));
+    g.insert("remainder", Value::Primitive(remainder));
+    g.insert("modulo", Value::Primitive(modulo));
+    g.insert("gcd", Value::Primitive(gcd));
+    g.insert("lcm", Value::Primitive(lcm));
+    define(&mut g, "numerator", |n: Number| n.numerator());
+    define(&mut g, "denominator", |n: Number| n.denominator());
+    define(&mut g, "floor", |n: Number| n.floor());
+    define(&mut g, "ceiling", |n: Number| n.ceiling());
+    define(&mut g, "truncate", |n: Number| n.truncate());
+    define(&mut g, "round", |n: Number| n.round());
+    define(&mut g, "exp", |n: Number| n.to_f64().exp());
+    g.insert("log", Value::Primitive(log));
+    define(&mut g, "sin", |n: Number| n.to_f64().sin());
+    define(&mut g, "cos", |n: Number| n.to_f64().cos());
+    define(&mut g, "tan", |n: Number| n.to_f64().tan());
+    g.insert("atan", Value::Primitive(atan));
+    define(&mut g, "sqrt", |n: Number| n.sqrt());
+    g.insert("square", Value::Primitive(square));
+    g.insert("expt", Value::Primitive(expt));
+    define(&mut g, "exact", |n: Number| n.to_exact());
+    define(&mut g, "inexact", |n: Number| n.to_inexact());
+    define(&mut g, "inexact->exact", |n: Number| n.to_exact());
+    define(&mut g, "exact->inexact", |n: Number| n.to_inexact());
+    define(&mut g, "display", |v: Value| {
+        print!("{}", v.display());
+        Value::Undefined
+    });
+    define(&mut g, "write", |v: Value| {
+        print!("{}", v);
+        Value::Undefined
+    });
+    define(&mut g, "newline", || {
+        println!();
+        Value::Undefined
+    });
+    define(&mut g, "symbol?", |v: Value| matches!(v, Value::Symbol(_)));
+    define(&mut g, "string?", |v: Value| matches!(v, Value::String(_)));
+    define(&mut g, "char?", |v: Value| matches!(v, Value::Char(_)));
+    g.insert("make-string", Value::Primitive(make_string));
+    g.insert("string", Value::Primitive(string));
+    define(&mut g, "string-length", |s: String| s.chars().count());
+    define(&mut g, "string-ref", |s: String, k: usize| {
+        s.chars()
+            .nth(k)
+            .ok_or("index out of range: string-ref")
+    });
+    define(&mut g, "substring", |s: String, start: usize, end: usize| {
+        if start > end || end > s.chars().count() {
+            return Err("index out of range: substring");
+        }
+        Ok(s.chars().skip(start).take(end - start).collect::<String>())
+    });
+    g.insert("string-append", Value::Primitive(string_append));
+    define(&mut g, "string-copy", |s: String| s);
+    define(&mut g, "string->list", |s: String| s.chars().collect::<Vec<_>>());
+    define(&mut g, "list->string", |chars: Vec<char>| chars.into_iter().collect::<String>());
+    define(&mut g, "string->symbol", |s: String| Value::Symbol(s));
+    g.insert("symbol->string", Value::Primitive(symbol_to_string));
+    g.insert("string->number", Value::Primitive(string_to_number));
+    g.insert("number->string", Value::Primitive(number_to_string));
+    define(&mut g, "string-upcase", |s: String| s.to_uppercase());
+    define(&mut g, "string-downcase", |s: String| s.to_lowercase());
+    g.insert("string=?", Value::Primitive(string_eq));
+    g.insert("string<?", Value::Primitive(string_lt));
+    g.insert("string>?", Value::Primitive(string_gt));
+    g.insert("string<=?", Value::Primitive(string_le));
+    g.insert("string>=?", Value::Primitive(string_ge));
+    define(&mut g, "char->integer", |c: char| c as u32);
+    define(&mut g, "integer->char", |k: usize| {
+        u32::try_from(k)
+            .ok()
+            .and_then(::std::char::from_u32)
+            .ok_or("invalid code point: integer->char")
+    });
+    g.insert("char=?", Value::Primitive(char_eq));
+    g.insert("char<?", Value::Primitive(char_lt));
+    g.insert("char>?", Value::Primitive(char_gt));
+    g.insert("char<=?", Value::Primitive(char_le));
+    g.insert("char>=?", Value::Primitive(char_ge));
+    define(&mut g, "char-alphabetic?", |c: char| c.is_alphabetic());
+    define(&mut g, "char-numeric?", |c: char| c.is_numeric());
+    define(&mut g, "char-whitespace?", |c: char| c.is_whitespace());
+    define(&mut g, "char-upper-case?", |c: char| c.is_uppercase());
+    define(&mut g, "char-lower-case?", |c: char| c.is_lowercase());
+    define(&mut g, "char-upcase", |c: char| c.to_uppercase().next().unwrap_or(c));
+    define(&mut g, "char-downcase", |c: char| c.to_lowercase().next().unwrap_or(c));
+    define(&mut g, "digit-value", |c: char| c.to_digit(10));
+    define(&mut g, "vector?", |v: Value| matches!(v, Value::Vector(_)));
+    g.insert("make-vector", Value::Primitive(make_vector));
+    g.insert("vector", Value::Primitive(vector));
+    define(&mut g, "vector-length", |v: Rc<Vector<Value>>| v.borrow().len());
+    define(&mut g, "vector-ref", |v: Rc<Vector<Value>>, k: usize| {
+        v.borrow()
+            .get(k)
+            .cloned()
+            .ok_or("index out of range: vector-ref")
+    });
+    g.insert("vector-set!", Value::Primitive(vector_set));
+    g.insert("vector->list", Value::Primitive(vector_to_list));
+    define(&mut g, "list->vector", |values: Vec<Value>| Value::vector(values));
+    g.insert("vector-fill!", Value::Primitive(vector_fill));
+    g.insert("vector-copy", Value::Primitive(vector_copy));
+    define(&mut g, "bytevector?", |v: Value| matches!(v, Value::Bytevector(_)));
+    g.insert("make-bytevector", Value::Primitive(make_bytevector));
+    g.insert("bytevector", Value::Primitive(bytevector));
+    define(&mut g, "bytevector-length", |b: Rc<Vector<u8>>| b.borrow().len());
+    define(&mut g, "bytevector-u8-ref", |b: Rc<Vector<u8>>, k: usize| {
+        b.borrow()
+            .get(k)
+            .cloned()
+            .ok_or("index out of range: bytevector-u8-ref")
+    });
+    g.insert("bytevector-u8-set!", Value::Primitive(bytevector_u8_set));
+    g.insert("bytevector->list", Value::Primitive(bytevector_to_list));
+    define(&mut g, "list->bytevector", |bytes: Vec<u8>| Value::bytevector(bytes));
+    g.insert("bytevector-fill!", Value::Primitive(bytevector_fill));
+    g.insert("bytevector-copy", Value::Primitive(bytevector_copy));
+    g.insert("call-with-current-continuation",
+             Value::Special(Special::CallCC));
+    g.insert("call/cc", Value::Special(Special::CallCC));
+    g.insert("raise", Value::Special(Special::Raise));
+    g.insert("raise-continuable",
+             Value::Special(Special::RaiseContinuable));
+    g.insert("with-exception-handler",
+             Value::Special(Special::WithExceptionHandler));
+    g.insert("error", Value::Special(Special::Error));
+    define(&mut g, "error-object?", |v: Value| matches!(v, Value::Error(_)));
+    g.insert("error-object-message",
+             Value::Primitive(error_object_message));
+    g.insert("error-object-irritants",
+             Value::Primitive(error_object_irritants));
     g
 }
 
-#[allow(unknown_lints, needless_pass_by_value)]
-fn print(args: Vec<Value>) -> Result<Value, String> {
+// 型付きの関数を手続きとして登録する。可変長や省略可能な引数をとるもの、ヒープの残りを
+// 確かめてから作るもの、書き換えできるかを確かめるものは、引数を直接扱う関数として登録する。
+fn define<F, Args>(g: &mut Global, name: &str, f: F)
+    where F: IntoProcedure<Args>
+{
+    g.insert(name, f.into_procedure(name));
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn print(args: Vec<Value>) -> Result<Value, SchemeError> {
     if !args.is_empty() {
         for v in args {
-            print!("{}", v)
+            print!("{}", v.display())
         }
     }
     println!();
     Ok(Value::Undefined)
 }
 
-#[allow(unknown_lints, needless_pass_by_value)]
-fn undefined(args: Vec<Value>) -> Result<Value, String> {
-    if !args.is_empty() {
-        return Err("wrong number of arguments: undefined".to_owned());
+#[allow(clippy::needless_pass_by_value)]
+fn set_car(args: Vec<Value>) -> Result<Value, SchemeError> {
+    if args.len() != 2 {
+        return Err(SchemeError::arity("set-car!", args.len()));
     }
+    mutable_pair_arg(&args[0], "set-car!")?.set_car(args[1].to_owned());
     Ok(Value::Undefined)
 }
 
-#[allow(unknown_lints, needless_pass_by_value)]
-fn cons(args: Vec<Value>) -> Result<Value, String> {
+#[allow(clippy::needless_pass_by_value)]
+fn set_cdr(args: Vec<Value>) -> Result<Value, SchemeError> {
     if args.len() != 2 {
-        return Err("wrong number of arguments: cons".to_owned());
+        return Err(SchemeError::arity("set-cdr!", args.len()));
     }
-    Ok(Value::cons(args[0].to_owned(), args[1].to_owned()))
+    mutable_pair_arg(&args[0], "set-cdr!")?.set_cdr(args[1].to_owned());
+    Ok(Value::Undefined)
 }
 
-#[allow(unknown_lints, needless_pass_by_value)]
-fn car(args: Vec<Value>) -> Result<Value, String> {
-    if args.len() != 1 {
-        return Err("wrong number of arguments: car".to_owned());
+#[allow(clippy::needless_pass_by_value)]
+fn list_set(args: Vec<Value>) -> Result<Value, SchemeError> {
+    if args.len() != 3 {
+        return Err(SchemeError::arity("list-set!", args.len()));
+    }
+    let k = index_arg(&args[1], "list-set!")?;
+    let mut list = args[0].to_owned();
+    let mut cycle = CycleCheck::new(&list);
+    for _ in 0..k {
+        list = list.cdr().ok_or_else(|| SchemeError::runtime("index out of range: list-set!"))?;
+        if cycle.step(&list) {
+            return Err(SchemeError::wrong_type("proper list", "list-set!", &args[0]));
+        }
+    }
+    match list {
+        Value::Cell(_) => {
+            mutable_pair_arg(&list, "list-set!")?.set_car(args[2].to_owned());
+            Ok(Value::Undefined)
+        }
+        _ => Err(SchemeError::runtime("index out of range: list-set!")),
     }
-    args[0]
-        .car()
-        .ok_or_else(|| "pair required: car".to_owned())
 }
 
-#[allow(unknown_lints, needless_pass_by_value)]
-fn cdr(args: Vec<Value>) -> Result<Value, String> {
+// 最後以外の引数のリストの末尾を、次の空でない引数につなぎ替える。
+#[allow(clippy::needless_pass_by_value)]
+fn append_bang(args: Vec<Value>) -> Result<Value, SchemeError> {
+    let mut result = Value::Nil;
+    let mut tail: Option<Rc<Pair>> = None;
+    for (i, arg) in args.iter().enumerate() {
+        let last = i + 1 == args.len();
+        if !last && *arg == Value::Nil {
+            continue;
+        }
+        match tail {
+            Some(ref pair) => {
+                pair.set_cdr(arg.to_owned());
+            }
+            None => result = arg.to_owned(),
+        }
+        if !last {
+            tail = Some(last_pair(arg, "append!")?);
+        }
+    }
+    Ok(result)
+}
+
+// リストのペアのcdrを逆向きにつなぎ替え、元の最後のペアを返す。
+#[allow(clippy::needless_pass_by_value)]
+fn reverse_bang(args: Vec<Value>) -> Result<Value, SchemeError> {
     if args.len() != 1 {
-        return Err("wrong number of arguments: cdr".to_owned());
+        return Err(SchemeError::arity("reverse!", args.len()));
     }
-    args[0]
-        .cdr()
-        .ok_or_else(|| "pair required: cdr".to_owned())
+    // 途中で失敗してリストが壊れないよう、書き換える前に全てのペアを確かめる。
+    let mut pairs = Vec::new();
+    let mut list = args[0].to_owned();
+    let mut cycle = CycleCheck::new(&list);
+    while let Value::Cell(_) = list {
+        let pair = mutable_pair_arg(&list, "reverse!")?;
+        list = pair.cdr();
+        pairs.push(pair);
+        if cycle.step(&list) {
+            return Err(SchemeError::wrong_type("proper list", "reverse!", &args[0]));
+        }
+    }
+    if list != Value::Nil {
+        return Err(SchemeError::wrong_type("proper list", "reverse!", &args[0]));
+    }
+    let mut result = Value::Nil;
+    for pair in pairs {
+        pair.set_cdr(result);
+        result = Value::Cell(pair);
+    }
+    Ok(result)
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn memq(args: Vec<Value>) -> Result<Value, SchemeError> {
+    member_by(&args, "memq", Value::is_eq)
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn memv(args: Vec<Value>) -> Result<Value, SchemeError> {
+    member_by(&args, "memv", Value::is_eqv)
 }
 
-// PartialEqをちゃんとしていないので正確なeq?ではない
-#[allow(unknown_lints, needless_pass_by_value)]
-fn eq_p(args: Vec<Value>) -> Result<Value, String> {
+#[allow(clippy::needless_pass_by_value)]
+fn member(args: Vec<Value>) -> Result<Value, SchemeError> {
+    member_by(&args, "member", Value::is_equal)
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn assq(args: Vec<Value>) -> Result<Value, SchemeError> {
+    assoc_by(&args, "assq", Value::is_eq)
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn assv(args: Vec<Value>) -> Result<Value, SchemeError> {
+    assoc_by(&args, "assv", Value::is_eqv)
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn assoc(args: Vec<Value>) -> Result<Value, SchemeError> {
+    assoc_by(&args, "assoc", Value::is_equal)
+}
+
+// listのうち、carがxと等しい最初の部分リストを返す。なければ#f。
+fn member_by(args: &[Value], name: &str, eq: fn(&Value, &Value) -> bool) -> Result<Value, SchemeError> {
     if args.len() != 2 {
-        return Err("wrong number of arguments: eq?".to_owned());
+        return Err(SchemeError::arity(name, args.len()));
     }
-    Ok(Value::Boolean(args[0] == args[1]))
+    let mut list = args[1].to_owned();
+    let mut cycle = CycleCheck::new(&list);
+    while let Value::Cell(cell) = list {
+        if eq(&args[0], &cell.car()) {
+            return Ok(Value::Cell(cell));
+        }
+        list = cell.cdr();
+        if cycle.step(&list) {
+            return Err(SchemeError::wrong_type("proper list", name, &args[1]));
+        }
+    }
+    if list != Value::Nil {
+        return Err(SchemeError::wrong_type("proper list", name, &args[1]));
+    }
+    Ok(Value::Boolean(false))
 }
 
-#[allow(unknown_lints, needless_pass_by_value)]
-fn pair_p(args: Vec<Value>) -> Result<Value, String> {
-    if args.len() != 1 {
-        return Err("wrong number of arguments: pair?".to_owned());
+// 連想リストのうち、キーがxと等しい最初の要素を返す。なければ#f。
+fn assoc_by(args: &[Value], name: &str, eq: fn(&Value, &Value) -> bool) -> Result<Value, SchemeError> {
+    if args.len() != 2 {
+        return Err(SchemeError::arity(name, args.len()));
     }
-    match args[0] {
-        Value::Cell(_) => Ok(Value::Boolean(true)),
-        _ => Ok(Value::Boolean(false)),
+    let mut list = args[1].to_owned();
+    let mut cycle = CycleCheck::new(&list);
+    while let Value::Cell(cell) = list {
+        match cell.car() {
+            Value::Cell(ref entry) if eq(&args[0], &entry.car()) => return Ok(cell.car()),
+            Value::Cell(_) => (),
+            _ => return Err(SchemeError::wrong_type("association list", name, &args[1])),
+        }
+        list = cell.cdr();
+        if cycle.step(&list) {
+            return Err(SchemeError::wrong_type("association list", name, &args[1]));
+        }
     }
+    if list != Value::Nil {
+        return Err(SchemeError::wrong_type("association list", name, &args[1]));
+    }
+    Ok(Value::Boolean(false))
 }
 
-#[allow(unknown_lints, needless_pass_by_value)]
-fn not(args: Vec<Value>) -> Result<Value, String> {
+#[allow(clippy::needless_pass_by_value)]
+fn list(args: Vec<Value>) -> Result<Value, SchemeError> {
+    if args.is_empty() {
+        Ok(Value::Nil)
+    } else {
+        Ok(vec2cons(&args, Value::Nil))
+    }
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn error_object_message(args: Vec<Value>) -> Result<Value, SchemeError> {
     if args.len() != 1 {
-        return Err("wrong number of arguments: not".to_owned());
+        return Err(SchemeError::arity("error-object-message", args.len()));
     }
     match args[0] {
-        Value::Boolean(false) => Ok(Value::Boolean(true)),
-        _ => Ok(Value::Boolean(false)),
+        Value::Error(ref error) => Ok(error.0.to_owned()),
+        _ => Err(SchemeError::wrong_type("error object", "error-object-message", &args[0])),
     }
 }
 
-#[allow(unknown_lints, needless_pass_by_value)]
-fn null_p(args: Vec<Value>) -> Result<Value, String> {
+#[allow(clippy::needless_pass_by_value)]
+fn error_object_irritants(args: Vec<Value>) -> Result<Value, SchemeError> {
     if args.len() != 1 {
-        return Err("wrong number of arguments: null?".to_owned());
+        return Err(SchemeError::arity("error-object-irritants", args.len()));
     }
     match args[0] {
-        Value::Nil => Ok(Value::Boolean(true)),
-        _ => Ok(Value::Boolean(false)),
+        Value::Error(ref error) => Ok(error.1.to_owned()),
+        _ => Err(SchemeError::wrong_type("error object", "error-object-irritants", &args[0])),
     }
 }
 
-#[allow(unknown_lints, needless_pass_by_value)]
-fn list(args: Vec<Value>) -> Result<Value, String> {
+#[allow(clippy::needless_pass_by_value)]
+fn add(args: Vec<Value>) -> Result<Value, SchemeError> {
+    fold_numeric_op(&args, "+", Number::from(0), |x, y| Ok(x.add(y)))
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn sub(args: Vec<Value>) -> Result<Value, SchemeError> {
     if args.is_empty() {
-        Ok(Value::Nil)
+        Err(SchemeError::arity("-", args.len()))
+    } else if args.len() == 1 {
+        Ok(Value::Number(number_arg(&args[0], "-")?.neg()))
     } else {
-        Ok(vec2cons(&args, Value::Nil))
+        let init = number_arg(&args[0], "-")?;
+        fold_numeric_op(&args[1..], "-", init, |x, y| Ok(x.sub(y)))
     }
 }
 
-#[allow(unknown_lints, needless_pass_by_value)]
-fn add(args: Vec<Value>) -> Result<Value, String> {
+#[allow(clippy::needless_pass_by_value)]
+fn mul(args: Vec<Value>) -> Result<Value, SchemeError> {
+    fold_numeric_op(&args, "*", Number::from(1), |x, y| Ok(x.mul(y)))
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn div(args: Vec<Value>) -> Result<Value, SchemeError> {
     if args.is_empty() {
-        Ok(Value::Integer(0))
+        Err(SchemeError::arity("/", args.len()))
+    } else if args.len() == 1 {
+        fold_numeric_op(&args, "/", Number::from(1), Number::div)
     } else {
-        fold_numeric_op(&args, |x, y| x + y).ok_or_else(|| {
-                                                            "all arguments must be integers: +"
-                                                                .to_owned()
-                                                        })
+        let init = number_arg(&args[0], "/")?;
+        fold_numeric_op(&args[1..], "/", init, Number::div)
+    }
+}
+
+fn fold_numeric_op<F>(args: &[Value], name: &str, init: Number, f: F) -> Result<Value, SchemeError>
+    where F: Fn(&Number, &Number) -> Result<Number, String>
+{
+    let mut acc = init;
+    for v in args {
+        let n = number_arg(v, name)?;
+        reserve_number(acc.bits() + n.bits())?;
+        acc = f(&acc, &n)?;
+    }
+    Ok(Value::Number(acc))
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn eq(args: Vec<Value>) -> Result<Value, SchemeError> {
+    fold_numeric_ord(&args, "=", |o| o == Ordering::Equal)
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn gt(args: Vec<Value>) -> Result<Value, SchemeError> {
+    fold_numeric_ord(&args, ">", |o| o == Ordering::Greater)
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn ge(args: Vec<Value>) -> Result<Value, SchemeError> {
+    fold_numeric_ord(&args, ">=", |o| o != Ordering::Less)
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn lt(args: Vec<Value>) -> Result<Value, SchemeError> {
+    fold_numeric_ord(&args, "<", |o| o == Ordering::Less)
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn le(args: Vec<Value>) -> Result<Value, SchemeError> {
+    fold_numeric_ord(&args, "<=", |o| o != Ordering::Greater)
+}
+
+// NaNとの比較は常に偽になる。
+fn fold_numeric_ord<F>(args: &[Value], name: &str, f: F) -> Result<Value, SchemeError>
+    where F: Fn(Ordering) -> bool
+{
+    if args.len() < 2 {
+        return Err(SchemeError::arity(name, args.len()));
     }
+    fold_ord(args,
+             name,
+             number_arg,
+             |x, y| x.compare(y).is_some_and(&f))
 }
 
-#[allow(unknown_lints, needless_pass_by_value)]
-fn sub(args: Vec<Value>) -> Result<Value, String> {
+#[allow(clippy::needless_pass_by_value)]
+fn max(args: Vec<Value>) -> Result<Value, SchemeError> {
+    fold_extremum(&args, "max", Ordering::Greater)
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn min(args: Vec<Value>) -> Result<Value, SchemeError> {
+    fold_extremum(&args, "min", Ordering::Less)
+}
+
+// 引数に不正確な数が含まれていれば、結果も不正確にする。
+fn fold_extremum(args: &[Value], name: &str, ordering: Ordering) -> Result<Value, SchemeError> {
     if args.is_empty() {
-        Err("wrong number of arguments: -".to_owned())
-    } else if args.len() == 1 {
-        match args[0] {
-            Value::Integer(num) => Ok(Value::Integer(-num)),
-            _ => Err("all arguments must be integers: -".to_owned()),
+        return Err(SchemeError::arity(name, args.len()));
+    }
+    let mut result = number_arg(&args[0], name)?;
+    let mut exact = result.is_exact();
+    for v in &args[1..] {
+        let n = number_arg(v, name)?;
+        exact = exact && n.is_exact();
+        if n.is_nan() || n.compare(&result) == Some(ordering) {
+            result = n;
         }
+    }
+    Ok(Value::Number(if exact { result } else { result.to_inexact() }))
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn quotient(args: Vec<Value>) -> Result<Value, SchemeError> {
+    binary_number_op(&args, "quotient", Number::quotient)
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn remainder(args: Vec<Value>) -> Result<Value, SchemeError> {
+    binary_number_op(&args, "remainder", Number::remainder)
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn modulo(args: Vec<Value>) -> Result<Value, SchemeError> {
+    binary_number_op(&args, "modulo", Number::modulo)
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn gcd(args: Vec<Value>) -> Result<Value, SchemeError> {
+    fold_numeric_op(&args, "gcd", Number::from(0), Number::gcd)
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn lcm(args: Vec<Value>) -> Result<Value, SchemeError> {
+    fold_numeric_op(&args, "lcm", Number::from(1), Number::lcm)
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn log(args: Vec<Value>) -> Result<Value, SchemeError> {
+    if args.len() == 2 {
+        let base = number_arg(&args[1], "log")?;
+        number_op(&args[..1], "log", |n| Ok(Number::Real(n.to_f64().log(base.to_f64()))))
     } else {
-        fold_numeric_op(&args, |x, y| x - y).ok_or_else(|| {
-                                                            "all arguments must be integers: -"
-                                                                .to_owned()
-                                                        })
+        number_op(&args, "log", |n| Ok(Number::Real(n.to_f64().ln())))
     }
 }
 
-#[allow(unknown_lints, needless_pass_by_value)]
-fn mul(args: Vec<Value>) -> Result<Value, String> {
-    if args.is_empty() {
-        Ok(Value::Integer(1))
+#[allow(clippy::needless_pass_by_value)]
+fn atan(args: Vec<Value>) -> Result<Value, SchemeError> {
+    if args.len() == 2 {
+        let x = number_arg(&args[1], "atan")?;
+        number_op(&args[..1], "atan", |y| Ok(Number::Real(y.to_f64().atan2(x.to_f64()))))
     } else {
-        fold_numeric_op(&args, |x, y| x + y).ok_or_else(|| {
-                                                            "all arguments must be integers: *"
-                                                                .to_owned()
-                                                        })
+        number_op(&args, "atan", |n| Ok(Number::Real(n.to_f64().atan())))
+    }
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn square(args: Vec<Value>) -> Result<Value, SchemeError> {
+    if let Some(Value::Number(n)) = args.first() {
+        reserve_number(n.bits().saturating_mul(2))?;
     }
+    number_op(&args, "square", |n| Ok(n.mul(n)))
 }
 
-fn fold_numeric_op<F>(args: &[Value], f: F) -> Option<Value>
-    where F: Fn(i32, i32) -> i32
+#[allow(clippy::needless_pass_by_value)]
+fn expt(args: Vec<Value>) -> Result<Value, SchemeError> {
+    if let (Some(Value::Number(base)), Some(&Value::Number(Number::Integer(n)))) = (args.first(), args.get(1)) {
+        reserve_number(base.bits().saturating_mul(n.unsigned_abs()))?;
+    }
+    binary_number_op(&args, "expt", Number::expt)
+}
+
+fn number_op<F>(args: &[Value], name: &str, f: F) -> Result<Value, SchemeError>
+    where F: Fn(&Number) -> Result<Number, String>
 {
-    if let Value::Integer(mut acc) = args[0] {
-        for i in args[1..].iter() {
-            if let Value::Integer(num) = *i {
-                acc = f(acc, num);
-            } else {
-                return None;
-            }
+    if args.len() != 1 {
+        return Err(SchemeError::arity(name, args.len()));
+    }
+    Ok(Value::Number(f(&number_arg(&args[0], name)?)?))
+}
+
+fn binary_number_op<F>(args: &[Value], name: &str, f: F) -> Result<Value, SchemeError>
+    where F: Fn(&Number, &Number) -> Result<Number, String>
+{
+    if args.len() != 2 {
+        return Err(SchemeError::arity(name, args.len()));
+    }
+    let (x, y) = (number_arg(&args[0], name)?, number_arg(&args[1], name)?);
+    reserve_number(x.bits() + y.bits())?;
+    Ok(Value::Number(f(&x, &y)?))
+}
+
+// 和、差、積、商などの結果のビット数は引数のビット数の和を超えないので、計算する前に
+// 多倍長整数を作れるだけのヒープが残っているか確かめる。小さな結果は確かめない。
+fn reserve_number(bits: u64) -> Result<(), SchemeError> {
+    if bits <= 128 {
+        return Ok(());
+    }
+    gc::reserve(gc::words(usize::try_from(bits.div_ceil(8)).unwrap_or(usize::MAX)))
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn make_string(args: Vec<Value>) -> Result<Value, SchemeError> {
+    if args.is_empty() || args.len() > 2 {
+        return Err(SchemeError::arity("make-string", args.len()));
+    }
+    let k = index_arg(&args[0], "make-string")?;
+    let c = match args.get(1) {
+        Some(v) => char_arg(v, "make-string")?,
+        None => ' ',
+    };
+    gc::reserve(gc::words(k.saturating_mul(c.len_utf8())))?;
+    let mut s = String::new();
+    k.checked_mul(c.len_utf8())
+        .and_then(|len| s.try_reserve_exact(len).ok())
+        .ok_or_else(|| allocation_error("make-string", k))?;
+    s.extend(::std::iter::repeat_n(c, k));
+    Ok(Value::string(&s))
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn string(args: Vec<Value>) -> Result<Value, SchemeError> {
+    let mut s = String::new();
+    for v in &args {
+        s.push(char_arg(v, "string")?);
+    }
+    Ok(Value::string(&s))
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn string_append(args: Vec<Value>) -> Result<Value, SchemeError> {
+    let mut result = String::new();
+    for v in &args {
+        result.push_str(&string_arg(v, "string-append")?);
+    }
+    Ok(Value::string(&result))
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn symbol_to_string(args: Vec<Value>) -> Result<Value, SchemeError> {
+    if args.len() != 1 {
+        return Err(SchemeError::arity("symbol->string", args.len()));
+    }
+    match args[0] {
+        Value::Symbol(ref s) => Ok(Value::string(s)),
+        _ => Err(SchemeError::wrong_type("symbol", "symbol->string", &args[0])),
+    }
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn string_to_number(args: Vec<Value>) -> Result<Value, SchemeError> {
+    if args.is_empty() || args.len() > 2 {
+        return Err(SchemeError::arity("string->number", args.len()));
+    }
+    let s = string_arg(&args[0], "string->number")?;
+    let radix = radix_arg(args.get(1), "string->number")?;
+    // 読んでいる間に作る数の大きさは文字列の長さと指数の上限で抑えられているので、
+    // 結果を返す前にヒープの上限を確かめる。
+    match Number::parse(&s, radix) {
+        Some(n) => {
+            reserve_number(n.bits())?;
+            Ok(Value::Number(n))
         }
-        Some(Value::Integer(acc))
-    } else {
-        None
+        None => Ok(Value::Boolean(false)),
     }
 }
 
-#[allow(unknown_lints, needless_pass_by_value)]
-fn eq(args: Vec<Value>) -> Result<Value, String> {
-    if args.len() < 2 {
-        return Err("wrong number of arguments: =".to_owned());
+#[allow(clippy::needless_pass_by_value)]
+fn number_to_string(args: Vec<Value>) -> Result<Value, SchemeError> {
+    if args.is_empty() || args.len() > 2 {
+        return Err(SchemeError::arity("number->string", args.len()));
     }
-    fold_numeric_ord(&args, |x, y| x == y).ok_or_else(|| {
-                                                          "all arguments must be integers: ="
-                                                              .to_owned()
-                                                      })
+    let n = number_arg(&args[0], "number->string")?;
+    let radix = radix_arg(args.get(1), "number->string")?;
+    n.to_string_radix(radix)
+        .map(|s| Value::string(&s))
+        .map_err(|e| SchemeError::from(format!("{}: number->string", e)))
 }
 
-#[allow(unknown_lints, needless_pass_by_value)]
-fn gt(args: Vec<Value>) -> Result<Value, String> {
-    if args.len() < 2 {
-        return Err("wrong number of arguments: >".to_owned());
+#[allow(clippy::needless_pass_by_value)]
+fn string_eq(args: Vec<Value>) -> Result<Value, SchemeError> {
+    fold_ord(&args, "string=?", string_arg, |x, y| x == y)
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn string_lt(args: Vec<Value>) -> Result<Value, SchemeError> {
+    fold_ord(&args, "string<?", string_arg, |x, y| x < y)
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn string_gt(args: Vec<Value>) -> Result<Value, SchemeError> {
+    fold_ord(&args, "string>?", string_arg, |x, y| x > y)
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn string_le(args: Vec<Value>) -> Result<Value, SchemeError> {
+    fold_ord(&args, "string<=?", string_arg, |x, y| x <= y)
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn string_ge(args: Vec<Value>) -> Result<Value, SchemeError> {
+    fold_ord(&args, "string>=?", string_arg, |x, y| x >= y)
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn char_eq(args: Vec<Value>) -> Result<Value, SchemeError> {
+    fold_ord(&args, "char=?", char_arg, |x, y| x == y)
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn char_lt(args: Vec<Value>) -> Result<Value, SchemeError> {
+    fold_ord(&args, "char<?", char_arg, |x, y| x < y)
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn char_gt(args: Vec<Value>) -> Result<Value, SchemeError> {
+    fold_ord(&args, "char>?", char_arg, |x, y| x > y)
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn char_le(args: Vec<Value>) -> Result<Value, SchemeError> {
+    fold_ord(&args, "char<=?", char_arg, |x, y| x <= y)
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn char_ge(args: Vec<Value>) -> Result<Value, SchemeError> {
+    fold_ord(&args, "char>=?", char_arg, |x, y| x >= y)
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn make_vector(args: Vec<Value>) -> Result<Value, SchemeError> {
+    if args.is_empty() || args.len() > 2 {
+        return Err(SchemeError::arity("make-vector", args.len()));
     }
-    fold_numeric_ord(&args, |x, y| x > y).ok_or_else(|| {
-                                                         "all arguments must be integers: >"
-                                                             .to_owned()
-                                                     })
+    let k = index_arg(&args[0], "make-vector")?;
+    let fill = args.get(1).cloned().unwrap_or(Value::Undefined);
+    gc::reserve(k.saturating_add(1))?;
+    Ok(Value::vector(filled(fill, k, "make-vector")?))
 }
 
-#[allow(unknown_lints, needless_pass_by_value)]
-fn ge(args: Vec<Value>) -> Result<Value, String> {
-    if args.len() < 2 {
-        return Err("wrong number of arguments: >=".to_owned());
+#[allow(clippy::needless_pass_by_value)]
+fn vector(args: Vec<Value>) -> Result<Value, SchemeError> {
+    Ok(Value::vector(args))
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn vector_set(args: Vec<Value>) -> Result<Value, SchemeError> {
+    if args.len() != 3 {
+        return Err(SchemeError::arity("vector-set!", args.len()));
+    }
+    let v = vector_arg(&args[0], "vector-set!")?;
+    let k = index_arg(&args[1], "vector-set!")?;
+    let mut v = v.borrow_mut()
+        .ok_or_else(|| SchemeError::wrong_type("mutable vector", "vector-set!", &args[0]))?;
+    match v.get_mut(k) {
+        Some(elem) => *elem = args[2].to_owned(),
+        None => return Err(SchemeError::runtime("index out of range: vector-set!")),
     }
-    fold_numeric_ord(&args, |x, y| x >= y).ok_or_else(|| {
-                                                          "all arguments must be integers: >="
-                                                              .to_owned()
-                                                      })
+    Ok(Value::Undefined)
 }
 
-#[allow(unknown_lints, needless_pass_by_value)]
-fn lt(args: Vec<Value>) -> Result<Value, String> {
-    if args.len() < 2 {
-        return Err("wrong number of arguments: <".to_owned());
+#[allow(clippy::needless_pass_by_value)]
+fn vector_to_list(args: Vec<Value>) -> Result<Value, SchemeError> {
+    if args.is_empty() || args.len() > 3 {
+        return Err(SchemeError::arity("vector->list", args.len()));
     }
-    fold_numeric_ord(&args, |x, y| x < y).ok_or_else(|| {
-                                                         "all arguments must be integers: <"
-                                                             .to_owned()
-                                                     })
+    let v = vector_arg(&args[0], "vector->list")?;
+    let v = v.borrow();
+    let (start, end) = range_args(&args[1..], v.len(), "vector->list")?;
+    Ok(vec2cons(&v[start..end], Value::Nil))
 }
 
-#[allow(unknown_lints, needless_pass_by_value)]
-fn le(args: Vec<Value>) -> Result<Value, String> {
-    if args.len() < 2 {
-        return Err("wrong number of arguments: <=".to_owned());
+#[allow(clippy::needless_pass_by_value)]
+fn vector_fill(args: Vec<Value>) -> Result<Value, SchemeError> {
+    if args.len() < 2 || args.len() > 4 {
+        return Err(SchemeError::arity("vector-fill!", args.len()));
+    }
+    let v = vector_arg(&args[0], "vector-fill!")?;
+    let mut v = v.borrow_mut()
+        .ok_or_else(|| SchemeError::wrong_type("mutable vector", "vector-fill!", &args[0]))?;
+    let (start, end) = range_args(&args[2..], v.len(), "vector-fill!")?;
+    for elem in &mut v[start..end] {
+        *elem = args[1].to_owned();
     }
-    fold_numeric_ord(&args, |x, y| x <= y).ok_or_else(|| {
-                                                          "all arguments must be integers:: <="
-                                                              .to_owned()
-                                                      })
+    Ok(Value::Undefined)
 }
 
-fn fold_numeric_ord<F>(args: &[Value], f: F) -> Option<Value>
-    where F: Fn(i32, i32) -> bool
-{
-    if let Value::Integer(mut current) = args[0] {
-        for i in args[1..].iter() {
-            if let Value::Integer(next) = *i {
-                if f(current, next) {
-                    current = next
-                } else {
-                    return Some(Value::Boolean(false));
-                }
-            } else {
-                return None;
-            }
+#[allow(clippy::needless_pass_by_value)]
+fn vector_copy(args: Vec<Value>) -> Result<Value, SchemeError> {
+    if args.is_empty() || args.len() > 3 {
+        return Err(SchemeError::arity("vector-copy", args.len()));
+    }
+    let v = vector_arg(&args[0], "vector-copy")?;
+    let v = v.borrow();
+    let (start, end) = range_args(&args[1..], v.len(), "vector-copy")?;
+    Ok(Value::vector(v[start..end].to_vec()))
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn make_bytevector(args: Vec<Value>) -> Result<Value, SchemeError> {
+    if args.is_empty() || args.len() > 2 {
+        return Err(SchemeError::arity("make-bytevector", args.len()));
+    }
+    let k = index_arg(&args[0], "make-bytevector")?;
+    let fill = match args.get(1) {
+        Some(v) => byte_arg(v, "make-bytevector")?,
+        None => 0,
+    };
+    gc::reserve(gc::words(k))?;
+    Ok(Value::bytevector(filled(fill, k, "make-bytevector")?))
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn bytevector(args: Vec<Value>) -> Result<Value, SchemeError> {
+    let mut bytes = Vec::with_capacity(args.len());
+    for v in &args {
+        bytes.push(byte_arg(v, "bytevector")?);
+    }
+    Ok(Value::bytevector(bytes))
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn bytevector_u8_set(args: Vec<Value>) -> Result<Value, SchemeError> {
+    if args.len() != 3 {
+        return Err(SchemeError::arity("bytevector-u8-set!", args.len()));
+    }
+    let b = bytevector_arg(&args[0], "bytevector-u8-set!")?;
+    let k = index_arg(&args[1], "bytevector-u8-set!")?;
+    let byte = byte_arg(&args[2], "bytevector-u8-set!")?;
+    let mut b = b.borrow_mut()
+        .ok_or_else(|| SchemeError::wrong_type("mutable bytevector", "bytevector-u8-set!", &args[0]))?;
+    match b.get_mut(k) {
+        Some(elem) => *elem = byte,
+        None => return Err(SchemeError::runtime("index out of range: bytevector-u8-set!")),
+    }
+    Ok(Value::Undefined)
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn bytevector_to_list(args: Vec<Value>) -> Result<Value, SchemeError> {
+    if args.is_empty() || args.len() > 3 {
+        return Err(SchemeError::arity("bytevector->list", args.len()));
+    }
+    let b = bytevector_arg(&args[0], "bytevector->list")?;
+    let b = b.borrow();
+    let (start, end) = range_args(&args[1..], b.len(), "bytevector->list")?;
+    let values = b[start..end]
+        .iter()
+        .map(|&byte| Value::integer(i64::from(byte)))
+        .collect::<Vec<_>>();
+    Ok(vec2cons(&values, Value::Nil))
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn bytevector_fill(args: Vec<Value>) -> Result<Value, SchemeError> {
+    if args.len() < 2 || args.len() > 4 {
+        return Err(SchemeError::arity("bytevector-fill!", args.len()));
+    }
+    let b = bytevector_arg(&args[0], "bytevector-fill!")?;
+    let byte = byte_arg(&args[1], "bytevector-fill!")?;
+    let mut b = b.borrow_mut()
+        .ok_or_else(|| SchemeError::wrong_type("mutable bytevector", "bytevector-fill!", &args[0]))?;
+    let (start, end) = range_args(&args[2..], b.len(), "bytevector-fill!")?;
+    for elem in &mut b[start..end] {
+        *elem = byte;
+    }
+    Ok(Value::Undefined)
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn bytevector_copy(args: Vec<Value>) -> Result<Value, SchemeError> {
+    if args.is_empty() || args.len() > 3 {
+        return Err(SchemeError::arity("bytevector-copy", args.len()));
+    }
+    let b = bytevector_arg(&args[0], "bytevector-copy")?;
+    let b = b.borrow();
+    let (start, end) = range_args(&args[1..], b.len(), "bytevector-copy")?;
+    Ok(Value::bytevector(b[start..end].to_vec()))
+}
+
+fn string_arg(v: &Value, name: &str) -> Result<Rc<String>, SchemeError> {
+    match *v {
+        Value::String(ref s) => Ok(s.clone()),
+        _ => Err(SchemeError::wrong_type("string", name, v)),
+    }
+}
+
+fn char_arg(v: &Value, name: &str) -> Result<char, SchemeError> {
+    match *v {
+        Value::Char(c) => Ok(c),
+        _ => Err(SchemeError::wrong_type("character", name, v)),
+    }
+}
+
+fn index_arg(v: &Value, name: &str) -> Result<usize, SchemeError> {
+    match *v {
+        Value::Number(Number::Integer(i)) if i >= 0 => Ok(i as usize),
+        _ => Err(SchemeError::wrong_type("non-negative integer", name, v)),
+    }
+}
+
+// 大きすぎる長さを指定されても、確保に失敗してプロセスが止まらないようにする。
+fn filled<T: Clone>(fill: T, k: usize, name: &str) -> Result<Vec<T>, SchemeError> {
+    let mut v = Vec::new();
+    v.try_reserve_exact(k).map_err(|_| allocation_error(name, k))?;
+    v.resize(k, fill);
+    Ok(v)
+}
+
+fn allocation_error(name: &str, k: usize) -> SchemeError {
+    SchemeError::runtime(&format!("{}: cannot allocate {} elements", name, k))
+}
+
+// 書き換えできるペア。引用されたリストなどの定数は書き換えできない。
+fn mutable_pair_arg(v: &Value, name: &str) -> Result<Rc<Pair>, SchemeError> {
+    match *v {
+        Value::Cell(ref pair) if pair.is_mutable() => Ok(pair.clone()),
+        Value::Cell(_) => Err(SchemeError::wrong_type("mutable pair", name, v)),
+        _ => Err(SchemeError::wrong_type("pair", name, v)),
+    }
+}
+
+// 空でない真正リストの最後のペア。append!でつなぎ替えるので書き換えできる必要がある。
+fn last_pair(v: &Value, name: &str) -> Result<Rc<Pair>, SchemeError> {
+    let mut pair = mutable_pair_arg(v, name)?;
+    let mut cycle = CycleCheck::new(v);
+    loop {
+        match pair.cdr() {
+            Value::Nil => return Ok(pair),
+            ref next @ Value::Cell(_) if !cycle.step(next) => pair = mutable_pair_arg(next, name)?,
+            _ => return Err(SchemeError::wrong_type("proper list", name, v)),
         }
-        Some(Value::Boolean(true))
-    } else {
-        None
     }
 }
+
+fn vector_arg(v: &Value, name: &str) -> Result<Rc<Vector<Value>>, SchemeError> {
+    match *v {
+        Value::Vector(ref v) => Ok(v.clone()),
+        _ => Err(SchemeError::wrong_type("vector", name, v)),
+    }
+}
+
+fn bytevector_arg(v: &Value, name: &str) -> Result<Rc<Vector<u8>>, SchemeError> {
+    match *v {
+        Value::Bytevector(ref b) => Ok(b.clone()),
+        _ => Err(SchemeError::wrong_type("bytevector", name, v)),
+    }
+}
+
+fn byte_arg(v: &Value, name: &str) -> Result<u8, SchemeError> {
+    match *v {
+        Value::Number(Number::Integer(i)) if (0..256).contains(&i) => Ok(i as u8),
+        _ => Err(SchemeError::wrong_type("byte", name, v)),
+    }
+}
+
+// vector->listなどが省略可能な引数として取るstartとend。
+fn range_args(args: &[Value], len: usize, name: &str) -> Result<(usize, usize), SchemeError> {
+    let start = match args.first() {
+        Some(v) => index_arg(v, name)?,
+        None => 0,
+    };
+    let end = match args.get(1) {
+        Some(v) => index_arg(v, name)?,
+        None => len,
+    };
+    if start > end || end > len {
+        return Err(SchemeError::from(format!("index out of range: {}", name)));
+    }
+    Ok((start, end))
+}
+
+fn number_arg(v: &Value, name: &str) -> Result<Number, SchemeError> {
+    match *v {
+        Value::Number(ref n) => Ok(n.to_owned()),
+        _ => Err(SchemeError::wrong_type("number", name, v)),
+    }
+}
+
+fn radix_arg(v: Option<&Value>, name: &str) -> Result<u32, SchemeError> {
+    match v {
+        None => Ok(10),
+        Some(&Value::Number(Number::Integer(i))) if [2, 8, 10, 16].contains(&i) => Ok(i as u32),
+        Some(v) => Err(SchemeError::wrong_type("radix 2, 8, 10 or 16", name, v)),
+    }
+}
+
+fn fold_ord<T, G, F>(args: &[Value], name: &str, arg: G, f: F) -> Result<Value, SchemeError>
+    where G: Fn(&Value, &str) -> Result<T, SchemeError>,
+          F: Fn(&T, &T) -> bool
+{
+    if args.is_empty() {
+        return Err(SchemeError::arity(name, args.len()));
+    }
+    let mut current = arg(&args[0], name)?;
+    let mut result = true;
+    for v in &args[1..] {
+        let next = arg(v, name)?;
+        result = result && f(&current, &next);
+        current = next;
+    }
+    Ok(Value::Boolean(result))
+}
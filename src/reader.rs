@@ -1,11 +1,25 @@
+use std::rc::Rc;
 use combine::*;
+use combine::primitives::{Consumed, Error, SourcePosition};
 use compiler::Ast;
+use number::Number;
+use span::{Source, Span};
+use value::CHAR_NAMES;
 
-pub fn read(input: &str) -> Result<(Vec<Ast>, &str), ParseError<&str>> {
+// 式の入れ子の深さの上限。リストやベクタ、引用符のたびに1つ深くなる。
+const MAX_DEPTH: usize = 200;
+
+type ReadResult<'a> = Result<Vec<Ast>, ParseError<State<&'a str>>>;
+
+/// `source`のテキストを読み、リストの式にその位置を記録する。
+pub fn read_source(source: &Rc<Source>) -> ReadResult<'_> {
+    let text = &source.text;
+    let source = Some(source);
     parser(whitespace)
-        .with(many1(parser(expression).skip(parser(whitespace))))
+        .with(many(parser(|input| expression(source, 0, input)).skip(parser(whitespace))))
         .skip(eof())
-        .parse(input.trim())
+        .parse(State::new(text.as_str()))
+        .map(|(ast, _)| ast)
 }
 
 fn whitespace<I>(input: I) -> ParseResult<(), I>
@@ -17,50 +31,157 @@ fn whitespace<I>(input: I) -> ParseResult<(), I>
     skip_many(skip_many1(char::space()).or(comment)).parse_stream(input)
 }
 
-fn expression<I>(input: I) -> ParseResult<Ast, I>
-    where I: Stream<Item = char>
+// 読み込んだ式に、その式が始まる位置を付ける。
+fn located<I, P>(source: Option<&Rc<Source>>, p: P) -> impl Parser<Input = I, Output = Ast>
+    where I: Stream<Item = char, Position = SourcePosition>,
+          P: Parser<Input = I, Output = Ast>
 {
-    between(token('('), token(')'), parser(list))
-        .or(parser(atom))
-        .or(parser(quote))
-        .or(parser(quasiquote))
-        .or(try(parser(unquote)).or(parser(unquote_splicing)))
+    let source = source.cloned();
+    position().and(p).map(move |(position, ast): (SourcePosition, Ast)| {
+        let span = source.as_ref().map(|source| {
+            Span {
+                source: source.clone(),
+                line: position.line as usize,
+                column: position.column as usize,
+            }
+        });
+        ast.with_span(span)
+    })
+}
+
+fn expression<I>(source: Option<&Rc<Source>>, depth: usize, input: I) -> ParseResult<Ast, I>
+    where I: Stream<Item = char, Position = SourcePosition>
+{
+    // 読み込みもコンパイルも再帰するので、入れ子が深すぎる入力はスタックを使い切る前に拒む。
+    if depth > MAX_DEPTH {
+        let error = Error::Message("expression nested too deeply".into());
+        return Err(Consumed::Consumed(ParseError::new(input.position(), error)));
+    }
+    let depth = depth + 1;
+    let list = between(token('('), token(')'), parser(move |input| list(source, depth, input)));
+    located(source, list)
+        .or(parser(move |input| atom(source, depth, input)))
+        .or(located(source, parser(move |input| quote(source, depth, input))))
+        .or(located(source, parser(move |input| quasiquote(source, depth, input))))
+        .or(try(located(source, parser(move |input| unquote(source, depth, input))))
+                .or(located(source, parser(move |input| unquote_splicing(source, depth, input)))))
         .parse_stream(input)
 }
 
-fn atom<I>(input: I) -> ParseResult<Ast, I>
-    where I: Stream<Item = char>
+fn atom<I>(source: Option<&Rc<Source>>, depth: usize, input: I) -> ParseResult<Ast, I>
+    where I: Stream<Item = char, Position = SourcePosition>
 {
-    try(parser(integer))
+    try(parser(number))
         .or(parser(symbol))
-        .or(parser(hash))
+        .or(parser(string))
+        .or(parser(|input| hash(source, depth, input)))
         .parse_stream(input)
 }
 
-fn hash<I>(input: I) -> ParseResult<Ast, I>
-    where I: Stream<Item = char>
+fn hash<I>(source: Option<&Rc<Source>>, depth: usize, input: I) -> ParseResult<Ast, I>
+    where I: Stream<Item = char, Position = SourcePosition>
 {
+    let boolean = any().and_then(|c| match c {
+                                     't' => Ok(Ast::Boolean(true)),
+                                     'f' => Ok(Ast::Boolean(false)),
+                                     _ => Err(Error::Message(format!("unknown # syntax: #{}", c).into())),
+                                 });
+    let elems = || {
+        char::spaces().with(many(parser(move |input| expression(source, depth, input)).skip(char::spaces())))
+    };
+    let vector = between(token('('), token(')'), elems()).map(Ast::Vector);
+    let bytevector = token('u')
+        .with(token('8'))
+        .with(between(token('('), token(')'), elems()))
+        .and_then(|elems: Vec<Ast>| {
+            elems
+                .iter()
+                .map(|elem| match *elem {
+                         Ast::Number(Number::Integer(i)) if (0..256).contains(&i) => Ok(i as u8),
+                         _ => Err(Error::Message("byte required in bytevector literal".into())),
+                     })
+                .collect::<Result<Vec<_>, _>>()
+                .map(Ast::Bytevector)
+        });
     token('#')
+        .with(vector.or(bytevector).or(parser(character)).or(boolean))
+        .parse_stream(input)
+}
+
+// #\a、#\space、#\x41の形式。#\の直後の1文字は英数字でなくてもよい。
+fn character<I>(input: I) -> ParseResult<Ast, I>
+    where I: Stream<Item = char>
+{
+    token('\\')
         .with(any())
-        .map(|c| match c {
-                 't' => Ast::Boolean(true),
-                 'f' => Ast::Boolean(false),
-                 _ => unimplemented!(),
-             })
+        .and(many::<String, _>(char::alpha_num()))
+        .and_then(|(c, rest)| {
+            if rest.is_empty() {
+                return Ok(Ast::Char(c));
+            }
+            let name = format!("{}{}", c, rest);
+            if let Some(&(_, ch)) = CHAR_NAMES.iter().find(|&&(n, _)| n == name) {
+                return Ok(Ast::Char(ch));
+            }
+            if c == 'x' {
+                if let Some(ch) = u32::from_str_radix(&rest, 16)
+                       .ok()
+                       .and_then(::std::char::from_u32) {
+                    return Ok(Ast::Char(ch));
+                }
+            }
+            Err(Error::Message(format!("unknown character name: {}", name).into()))
+        })
         .parse_stream(input)
 }
 
-fn integer<I>(input: I) -> ParseResult<Ast, I>
+fn string<I>(input: I) -> ParseResult<Ast, I>
     where I: Stream<Item = char>
 {
-    let unsigned = many1::<String, _>(char::digit());
-    let positive = unsigned
-        .to_owned()
-        .map(|s| Ast::Integer(s.parse::<u32>().unwrap() as i32));
-    let negative = token('-')
-        .with(unsigned)
-        .map(|s| Ast::Integer(-(s.parse::<u32>().unwrap() as i32)));
-    negative.or(positive).parse_stream(input)
+    let plain = satisfy(|c| c != '"' && c != '\\').map(Some);
+    let hex = token('x')
+        .with(many1::<String, _>(char::hex_digit()))
+        .skip(token(';'))
+        .and_then(|s| {
+                      u32::from_str_radix(&s, 16)
+                          .ok()
+                          .and_then(::std::char::from_u32)
+                          .map(Some)
+                          .ok_or_else(|| Error::Message("invalid hex escape".into()))
+                  });
+    // 行末の\は、前後の空白とともに読み飛ばす。
+    let intraline = || skip_many(one_of(" \t".chars()));
+    let line_continuation = intraline()
+        .with(token('\n'))
+        .skip(intraline())
+        .map(|_| None);
+    let mnemonic = any().and_then(|c| match c {
+                                      'a' => Ok(Some('\x07')),
+                                      'b' => Ok(Some('\x08')),
+                                      't' => Ok(Some('\t')),
+                                      'n' => Ok(Some('\n')),
+                                      'r' => Ok(Some('\r')),
+                                      '"' | '\\' | '|' => Ok(Some(c)),
+                                      _ => Err(Error::Message("unknown escape sequence".into())),
+                                  });
+    let escape = token('\\').with(hex.or(line_continuation).or(mnemonic));
+    between(token('"'), token('"'), many::<Vec<_>, _>(plain.or(escape)))
+        .map(|chars: Vec<Option<char>>| Ast::String(chars.into_iter().flatten().collect()))
+        .parse_stream(input)
+}
+
+// 区切り文字までを1つの字句として読み、数の構文として解釈できるか試す。
+// 解釈できなければ失敗し、atomはシンボルなどとして読み直す。
+fn number<I>(input: I) -> ParseResult<Ast, I>
+    where I: Stream<Item = char>
+{
+    many1::<String, _>(satisfy(|c: char| !c.is_whitespace() && !"()\";'`,".contains(c)))
+        .and_then(|s| {
+                      Number::parse(&s, 10)
+                          .map(Ast::Number)
+                          .ok_or_else(|| Error::Message("not a number".into()))
+                  })
+        .parse_stream(input)
 }
 
 fn symbol<I>(input: I) -> ParseResult<Ast, I>
@@ -74,58 +195,101 @@ fn symbol<I>(input: I) -> ParseResult<Ast, I>
         .to_owned()
         .or(char::digit())
         .or(special_subsequent);
+    let ellipsis = try(char::string("...")).map(Ast::new_symbol);
     initial
         .and(many(subsequent))
         .map(|(i, s): (char, String)| Ast::Symbol(format!("{}{}", i, s)))
         .or(peculiar_identifier.map(|s: char| Ast::Symbol(s.to_string())))
+        .or(ellipsis)
         .parse_stream(input)
 }
 
-fn quote<I>(input: I) -> ParseResult<Ast, I>
-    where I: Stream<Item = char>
+fn quote<I>(source: Option<&Rc<Source>>, depth: usize, input: I) -> ParseResult<Ast, I>
+    where I: Stream<Item = char, Position = SourcePosition>
 {
     token('\'')
-        .with(parser(expression))
+        .with(parser(|input| expression(source, depth, input)))
         .map(|val| Ast::new_list(&[Ast::new_symbol("quote"), val], Ast::Nil))
         .parse_stream(input)
 }
 
-fn quasiquote<I>(input: I) -> ParseResult<Ast, I>
-    where I: Stream<Item = char>
+fn quasiquote<I>(source: Option<&Rc<Source>>, depth: usize, input: I) -> ParseResult<Ast, I>
+    where I: Stream<Item = char, Position = SourcePosition>
 {
     token('`')
-        .with(parser(expression))
+        .with(parser(|input| expression(source, depth, input)))
         .map(|val| Ast::new_list(&[Ast::new_symbol("quasiquote"), val], Ast::Nil))
         .parse_stream(input)
 }
 
-fn unquote<I>(input: I) -> ParseResult<Ast, I>
-    where I: Stream<Item = char>
+fn unquote<I>(source: Option<&Rc<Source>>, depth: usize, input: I) -> ParseResult<Ast, I>
+    where I: Stream<Item = char, Position = SourcePosition>
 {
     token(',')
-        .with(parser(expression))
+        .with(parser(|input| expression(source, depth, input)))
         .map(|val| Ast::new_list(&[Ast::new_symbol("unquote"), val], Ast::Nil))
         .parse_stream(input)
 }
 
-fn unquote_splicing<I>(input: I) -> ParseResult<Ast, I>
-    where I: Stream<Item = char>
+fn unquote_splicing<I>(source: Option<&Rc<Source>>, depth: usize, input: I) -> ParseResult<Ast, I>
+    where I: Stream<Item = char, Position = SourcePosition>
 {
     token(',')
         .with(token('@'))
-        .with(parser(expression))
+        .with(parser(|input| expression(source, depth, input)))
         .map(|val| Ast::new_list(&[Ast::new_symbol("unquote-splicing"), val], Ast::Nil))
         .parse_stream(input)
 }
 
-fn list<I>(input: I) -> ParseResult<Ast, I>
-    where I: Stream<Item = char>
+fn list<I>(source: Option<&Rc<Source>>, depth: usize, input: I) -> ParseResult<Ast, I>
+    where I: Stream<Item = char, Position = SourcePosition>
 {
-    let former = char::spaces().with(many(parser(expression).skip(char::spaces())));
-    let dotted = token('.').skip(char::spaces()).with(parser(expression));
+    let expression = || parser(move |input| expression(source, depth, input));
+    let former = char::spaces().with(many(expression().skip(char::spaces())));
+    let dotted = token('.').skip(char::spaces()).with(expression());
     let nil = char::spaces().map(|_| Ast::Nil);
     former
         .and(dotted.or(nil))
         .map(|(former, last): (Vec<_>, _)| Ast::new_list(&former, last))
         .parse_stream(input)
 }
+
+// 括弧や文字列が閉じていない、または引用符の後に式がない入力を、
+// 続きの行を待つべき不完全な入力として判定する。REPLが使う。
+pub fn is_incomplete(input: &str) -> bool {
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut pending_quote = false;
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        if in_string {
+            match c {
+                '\\' => {
+                    chars.next();
+                }
+                '"' => in_string = false,
+                _ => (),
+            }
+            continue;
+        }
+        match c {
+            ';' => {
+                while chars.peek().is_some_and(|&c| c != '\n') {
+                    chars.next();
+                }
+                continue;
+            }
+            c if c.is_whitespace() => continue,
+            '"' => in_string = true,
+            '(' => depth += 1,
+            ')' => depth = depth.saturating_sub(1),
+            '#' if chars.peek() == Some(&'\\') => {
+                chars.next();
+                chars.next();
+            }
+            _ => (),
+        }
+        pending_quote = c == '\'' || c == '`' || c == ',' || (c == '@' && pending_quote);
+    }
+    depth > 0 || in_string || pending_quote
+}
@@ -0,0 +1,103 @@
+use std::borrow::Cow;
+use std::env;
+use std::path::PathBuf;
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::{Context, Editor, Helper};
+use secd::{Interpreter, Value, is_incomplete};
+
+// 識別子の区切りになる文字。補完する語の先頭を探すのに使う。
+const DELIMITERS: &str = "()'`,\"";
+
+struct ReplHelper {
+    names: Vec<String>,
+}
+
+impl Completer for ReplHelper {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, _: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let start = line[..pos]
+            .rfind(|c: char| c.is_whitespace() || DELIMITERS.contains(c))
+            .map_or(0, |i| i + 1);
+        let prefix = &line[start..pos];
+        let mut candidates = self.names
+            .iter()
+            .filter(|name| name.starts_with(prefix))
+            .map(|name| {
+                     Pair {
+                         display: name.to_owned(),
+                         replacement: name.to_owned(),
+                     }
+                 })
+            .collect::<Vec<_>>();
+        candidates.sort_by(|a, b| a.display.cmp(&b.display));
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper {
+    fn highlight_prompt<'b, 's: 'b, 'p: 'b>(&'s self, prompt: &'p str, _: bool) -> Cow<'b, str> {
+        Cow::Borrowed(prompt)
+    }
+}
+
+// 括弧が閉じていなければEnterで評価せず、次の行の入力を続ける。
+impl Validator for ReplHelper {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        if is_incomplete(ctx.input()) {
+            Ok(ValidationResult::Incomplete)
+        } else {
+            Ok(ValidationResult::Valid(None))
+        }
+    }
+}
+
+impl Helper for ReplHelper {}
+
+fn history_path() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".secd_history"))
+}
+
+pub fn run(mut interpreter: Interpreter) -> rustyline::Result<()> {
+    let mut editor = Editor::<ReplHelper, DefaultHistory>::new()?;
+    editor.set_helper(Some(ReplHelper { names: interpreter.names() }));
+    let history = history_path();
+    if let Some(ref path) = history {
+        // 初回の起動では履歴のファイルがないので、読み込めなくても無視する。
+        let _ = editor.load_history(path);
+    }
+    loop {
+        match editor.readline("secd> ") {
+            Ok(line) => {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                editor.add_history_entry(line.as_str())?;
+                match interpreter.eval_source("<repl>", &line) {
+                    Ok(Value::Undefined) => (),
+                    Ok(value) => println!("{}", value),
+                    Err(e) => eprintln!("{}", e),
+                }
+                if let Some(helper) = editor.helper_mut() {
+                    helper.names = interpreter.names();
+                }
+            }
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e),
+        }
+    }
+    if let Some(ref path) = history {
+        editor.save_history(path)?;
+    }
+    Ok(())
+}
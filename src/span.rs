@@ -0,0 +1,54 @@
+use std::fmt;
+use std::rc::Rc;
+
+/// 読み込んだプログラムのテキストと、エラーの表示に使うファイル名などの名前。
+#[derive(Debug, PartialEq)]
+pub struct Source {
+    pub name: String,
+    pub text: String,
+}
+
+impl Source {
+    pub fn new(name: &str, text: &str) -> Rc<Source> {
+        Rc::new(Source {
+                    name: name.to_owned(),
+                    text: text.to_owned(),
+                })
+    }
+}
+
+/// 式が始まる位置。行と列は1から数え、列は文字単位。
+#[derive(Debug, Clone)]
+pub struct Span {
+    pub source: Rc<Source>,
+    pub line: usize,
+    pub column: usize,
+}
+
+// 位置は式の等しさに影響しない。syntax-rulesのリテラルの照合などでは
+// 別の場所に書かれた同じ式を等しいものとして扱う必要がある。
+impl PartialEq for Span {
+    fn eq(&self, _: &Span) -> bool {
+        true
+    }
+}
+
+impl Span {
+    /// 位置を含む行と、その列を指す`^`の2行。行が見つからなければNone。
+    pub fn snippet(&self) -> Option<(String, String)> {
+        let line = self.source.text.lines().nth(self.line.checked_sub(1)?)?;
+        // タブで字下げされた行でも`^`の位置がずれないよう、タブはそのまま残す。
+        let caret = line.chars()
+            .take(self.column.saturating_sub(1))
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .chain(Some('^'))
+            .collect();
+        Some((line.to_owned(), caret))
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.source.name, self.line, self.column)
+    }
+}
@@ -0,0 +1,377 @@
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use compiler::Ast;
+
+// マクロ展開ごとに異なる値を割り当て、同じ名前でも別の展開で導入された識別子を区別する。
+static STAMP: AtomicUsize = AtomicUsize::new(0);
+
+// syntax-rulesのテンプレートによって導入された識別子。
+// env_lenはマクロを定義した時点のコンパイル時環境の深さで、束縛を探す際は
+// その範囲だけを見ることで、使用側の束縛に捕捉されないようにする。
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alias {
+    pub name: Ast,
+    stamp: usize,
+    pub env_len: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxRules {
+    ellipsis: Ast,
+    literals: Vec<Ast>,
+    rules: Vec<(Ast, Ast)>,
+    pub env_len: usize,
+}
+
+#[derive(Debug, Clone)]
+enum Binding {
+    One(Ast),
+    Many(Vec<Binding>),
+}
+
+type Bindings = Vec<(Ast, Binding)>;
+
+impl SyntaxRules {
+    // specは(syntax-rules (literal ...) (pattern template) ...)の形。
+    // R7RSにならい、(syntax-rules ellipsis (literal ...) ...)で省略記号を変更できる。
+    pub fn new(spec: &Ast, env_len: usize) -> Result<SyntaxRules, String> {
+        let (elems, last) = spec.flatten();
+        if last != Ast::Nil || elems.is_empty() ||
+           elems[0].identifier_name() != Some("syntax-rules") {
+            return Err("malformed syntax-rules".to_owned());
+        }
+        let (ellipsis, rest) = match elems.get(1) {
+            Some(id) if id.identifier_name().is_some() => (id.to_owned(), &elems[2..]),
+            _ => (Ast::new_symbol("..."), &elems[1..]),
+        };
+        let (literals, rules) = match rest.split_first() {
+            Some((literals, rules)) => (literals, rules),
+            None => return Err("malformed syntax-rules".to_owned()),
+        };
+        let (literals, last) = literals.flatten();
+        if last != Ast::Nil || literals.iter().any(|l| l.identifier_name().is_none()) {
+            return Err("malformed syntax-rules: literals".to_owned());
+        }
+        let mut parsed = Vec::with_capacity(rules.len());
+        for rule in rules {
+            let (rule, last) = rule.flatten();
+            if last != Ast::Nil || rule.len() != 2 {
+                return Err("malformed syntax-rules: rule".to_owned());
+            }
+            match rule[0] {
+                Ast::List(..) | Ast::Nil => (),
+                _ => return Err("malformed syntax-rules: pattern".to_owned()),
+            }
+            parsed.push((rule[0].to_owned(), rule[1].to_owned()));
+        }
+        Ok(SyntaxRules {
+               ellipsis,
+               literals,
+               rules: parsed,
+               env_len,
+           })
+    }
+
+    // same_bindingはリテラルと入力の識別子が同じ束縛を指すかどうか(free-identifier=?)を返す。
+    // リテラルはマクロを定義した環境で、入力は使用した環境で束縛を探す。
+    pub fn expand(&self, form: &Ast, same_binding: &dyn Fn(&Ast, &Ast) -> bool) -> Result<Ast, String> {
+        let (input, input_last) = form.flatten();
+        for (pattern, template) in &self.rules {
+            let (pattern, pattern_last) = pattern.flatten();
+            let mut bindings = Vec::new();
+            // パターンの先頭はマクロのキーワードの位置なので無視する。
+            if !pattern.is_empty() && !input.is_empty() &&
+               self.match_seq(&pattern[1..],
+                              &pattern_last,
+                              &input[1..],
+                              &input_last,
+                              &mut bindings,
+                              same_binding) {
+                let stamp = STAMP.fetch_add(1, Ordering::Relaxed);
+                return self.instantiate(template, &mut bindings, stamp, true);
+            }
+        }
+        let name = input
+            .first()
+            .and_then(|head| head.identifier_name())
+            .unwrap_or("syntax-rules");
+        Err(format!("no matching syntax rule: {}", name))
+    }
+
+    fn is_ellipsis(&self, ast: &Ast) -> bool {
+        *ast == self.ellipsis
+    }
+
+    fn is_literal(&self, ast: &Ast) -> bool {
+        self.literals.iter().any(|l| l == ast)
+    }
+
+    fn match_pattern(&self,
+                     pattern: &Ast,
+                     input: &Ast,
+                     bindings: &mut Bindings,
+                     same_binding: &dyn Fn(&Ast, &Ast) -> bool)
+                     -> bool {
+        match *pattern {
+            Ast::Symbol(_) | Ast::Alias(_) => {
+                if self.is_literal(pattern) {
+                    input.identifier_name().is_some() && same_binding(pattern, input)
+                } else {
+                    if pattern.identifier_name() != Some("_") {
+                        bindings.push((pattern.to_owned(), Binding::One(input.to_owned())));
+                    }
+                    true
+                }
+            }
+            Ast::List(..) | Ast::Nil => {
+                let (pattern, pattern_last) = pattern.flatten();
+                let (input, input_last) = match *input {
+                    Ast::List(..) | Ast::Nil => input.flatten(),
+                    ref atom => (Vec::new(), atom.to_owned()),
+                };
+                self.match_seq(&pattern, &pattern_last, &input, &input_last, bindings, same_binding)
+            }
+            Ast::Vector(ref pattern) => {
+                match *input {
+                    Ast::Vector(ref input) => {
+                        self.match_seq(pattern, &Ast::Nil, input, &Ast::Nil, bindings, same_binding)
+                    }
+                    _ => false,
+                }
+            }
+            ref datum => datum == input,
+        }
+    }
+
+    fn match_seq(&self,
+                 pattern: &[Ast],
+                 pattern_last: &Ast,
+                 input: &[Ast],
+                 input_last: &Ast,
+                 bindings: &mut Bindings,
+                 same_binding: &dyn Fn(&Ast, &Ast) -> bool)
+                 -> bool {
+        match pattern.iter().position(|p| self.is_ellipsis(p)) {
+            Some(k) if k > 0 => {
+                let (before, repeated, after) = (&pattern[..k - 1], &pattern[k - 1], &pattern[k + 1..]);
+                if input.len() < before.len() + after.len() {
+                    return false;
+                }
+                let middle_end = input.len() - after.len();
+                if !self.match_each(before, &input[..before.len()], bindings, same_binding) {
+                    return false;
+                }
+                let mut matches = Vec::new();
+                for item in &input[before.len()..middle_end] {
+                    let mut b = Vec::new();
+                    if !self.match_pattern(repeated, item, &mut b, same_binding) {
+                        return false;
+                    }
+                    matches.push(b);
+                }
+                for var in self.pattern_vars(repeated) {
+                    let seq = matches
+                        .iter()
+                        .map(|b| lookup(b, &var).cloned().unwrap_or(Binding::Many(Vec::new())))
+                        .collect();
+                    bindings.push((var, Binding::Many(seq)));
+                }
+                self.match_each(after, &input[middle_end..], bindings, same_binding) &&
+                self.match_last(pattern_last, &[], input_last, bindings, same_binding)
+            }
+            Some(_) => false,
+            None => {
+                if input.len() < pattern.len() {
+                    return false;
+                }
+                self.match_each(pattern, &input[..pattern.len()], bindings, same_binding) &&
+                self.match_last(pattern_last, &input[pattern.len()..], input_last, bindings, same_binding)
+            }
+        }
+    }
+
+    fn match_each(&self,
+                  pattern: &[Ast],
+                  input: &[Ast],
+                  bindings: &mut Bindings,
+                  same_binding: &dyn Fn(&Ast, &Ast) -> bool)
+                  -> bool {
+        pattern
+            .iter()
+            .zip(input)
+            .all(|(p, i)| self.match_pattern(p, i, bindings, same_binding))
+    }
+
+    // パターンの末尾(ドット対のcdr部)と、入力の残りの要素および末尾を照合する。
+    fn match_last(&self,
+                  pattern_last: &Ast,
+                  rest: &[Ast],
+                  input_last: &Ast,
+                  bindings: &mut Bindings,
+                  same_binding: &dyn Fn(&Ast, &Ast) -> bool)
+                  -> bool {
+        if *pattern_last == Ast::Nil {
+            rest.is_empty() && *input_last == Ast::Nil
+        } else {
+            let rest = if rest.is_empty() {
+                input_last.to_owned()
+            } else {
+                Ast::new_list(rest, input_last.to_owned())
+            };
+            self.match_pattern(pattern_last, &rest, bindings, same_binding)
+        }
+    }
+
+    fn pattern_vars(&self, pattern: &Ast) -> Vec<Ast> {
+        let mut vars = Vec::new();
+        self.collect_vars(pattern, &mut vars);
+        vars
+    }
+
+    fn collect_vars(&self, pattern: &Ast, vars: &mut Vec<Ast>) {
+        match *pattern {
+            Ast::Symbol(_) | Ast::Alias(_) if !self.is_literal(pattern) &&
+                                               !self.is_ellipsis(pattern) &&
+                                               pattern.identifier_name() != Some("_") => {
+                vars.push(pattern.to_owned());
+            }
+            Ast::List(ref elems, ref last, _) => {
+                for elem in elems {
+                    self.collect_vars(elem, vars);
+                }
+                self.collect_vars(last, vars);
+            }
+            Ast::Vector(ref elems) => {
+                for elem in elems {
+                    self.collect_vars(elem, vars);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    // escapeが偽の場合、(... template)の内側として省略記号を特別扱いしない。
+    fn instantiate(&self,
+                   template: &Ast,
+                   bindings: &mut Bindings,
+                   stamp: usize,
+                   escape: bool)
+                   -> Result<Ast, String> {
+        match *template {
+            Ast::Symbol(_) | Ast::Alias(_) => {
+                match lookup(bindings, template) {
+                    Some(Binding::One(ast)) => Ok(ast.to_owned()),
+                    Some(Binding::Many(_)) => {
+                        Err("pattern variable used without ellipsis in template".to_owned())
+                    }
+                    None => {
+                        Ok(Ast::Alias(Rc::new(Alias {
+                                                  name: template.to_owned(),
+                                                  stamp,
+                                                  env_len: self.env_len,
+                                              })))
+                    }
+                }
+            }
+            Ast::List(..) => {
+                let (elems, last) = template.flatten();
+                if escape && elems.len() == 2 && self.is_ellipsis(&elems[0]) && last == Ast::Nil {
+                    return self.instantiate(&elems[1], bindings, stamp, false);
+                }
+                let result = self.instantiate_seq(&elems, bindings, stamp, escape)?;
+                let last = self.instantiate(&last, bindings, stamp, escape)?;
+                if result.is_empty() {
+                    Ok(last)
+                } else {
+                    Ok(Ast::new_list(&result, last))
+                }
+            }
+            Ast::Vector(ref elems) => {
+                self.instantiate_seq(elems, bindings, stamp, escape)
+                    .map(Ast::Vector)
+            }
+            ref datum => Ok(datum.to_owned()),
+        }
+    }
+
+    // 要素の並びを展開する。後ろに省略記号が続く要素は、その数だけ深く繰り返す。
+    fn instantiate_seq(&self,
+                       elems: &[Ast],
+                       bindings: &mut Bindings,
+                       stamp: usize,
+                       escape: bool)
+                       -> Result<Vec<Ast>, String> {
+        let mut result = Vec::new();
+        let mut i = 0;
+        while i < elems.len() {
+            let mut depth = 0;
+            while escape && i + depth + 1 < elems.len() &&
+                  self.is_ellipsis(&elems[i + depth + 1]) {
+                depth += 1;
+            }
+            if depth == 0 {
+                result.push(self.instantiate(&elems[i], bindings, stamp, escape)?);
+            } else {
+                self.instantiate_ellipsis(&elems[i], bindings, stamp, depth, &mut result)?;
+            }
+            i += depth + 1;
+        }
+        Ok(result)
+    }
+
+    fn instantiate_ellipsis(&self,
+                            template: &Ast,
+                            bindings: &mut Bindings,
+                            stamp: usize,
+                            depth: usize,
+                            result: &mut Vec<Ast>)
+                            -> Result<(), String> {
+        let mut vars = Vec::new();
+        self.collect_vars(template, &mut vars);
+        // 繰り返す変数と、その束縛の位置と長さ。
+        let seqs = vars.iter()
+            .filter_map(|var| {
+                let i = position(bindings, var)?;
+                match bindings[i].1 {
+                    Binding::Many(ref seq) => Some((var, i, seq.len())),
+                    Binding::One(_) => None,
+                }
+            })
+            .collect::<Vec<_>>();
+        let len = match seqs.first() {
+            Some(&(_, _, len)) => len,
+            None => return Err("no pattern variable before ellipsis in template".to_owned()),
+        };
+        if seqs.iter().any(|&(_, _, n)| n != len) {
+            return Err("pattern variables with different lengths under ellipsis".to_owned());
+        }
+        // 繰り返しごとに束縛全体を複製せず、i番目の要素の束縛を積んでから取り除く。
+        let base = bindings.len();
+        for i in 0..len {
+            for &(var, j, _) in &seqs {
+                let element = match bindings[j].1 {
+                    Binding::Many(ref seq) => seq[i].to_owned(),
+                    Binding::One(_) => continue,
+                };
+                bindings.push((var.to_owned(), element));
+            }
+            let instantiated = if depth == 1 {
+                self.instantiate(template, bindings, stamp, true).map(|ast| result.push(ast))
+            } else {
+                self.instantiate_ellipsis(template, bindings, stamp, depth - 1, result)
+            };
+            bindings.truncate(base);
+            instantiated?;
+        }
+        Ok(())
+    }
+}
+
+// 後から追加された束縛が優先される。
+fn lookup<'a>(bindings: &'a Bindings, var: &Ast) -> Option<&'a Binding> {
+    position(bindings, var).map(|i| &bindings[i].1)
+}
+
+fn position(bindings: &Bindings, var: &Ast) -> Option<usize> {
+    bindings.iter().rposition(|(v, _)| v == var)
+}
@@ -1,91 +1,684 @@
+use std::cell::{BorrowError, Ref, RefCell, RefMut};
+use std::collections::HashSet;
 use std::fmt;
+use std::mem;
 use std::rc::Rc;
-use vm::{SharedCode, Env};
+use vm::{Env, Frame, Lambda, Special, Continuation};
+use error::SchemeError;
+use gc::{self, Child};
 use compiler::Ast;
-use reader::read;
+use syntax::SyntaxRules;
+use number::Number;
+use native::{Arity, Native};
 
+// 構文として使える値の入れ子の深さの上限。define-macroの手続きが返す値はいくらでも深くなりうる。
+const MAX_SYNTAX_DEPTH: usize = 1000;
+
+// 表示する入れ子の深さの上限。これより深い部分は...と表示する。
+const MAX_PRINT_DEPTH: usize = 1000;
+
+#[allow(unpredictable_function_pointer_comparisons)]
 #[derive(Debug, Clone, PartialEq)]
 pub enum Value {
     Nil,
     Boolean(bool),
-    Integer(i32),
+    Number(Number),
     Symbol(String),
-    Cell(Rc<(Value, Value)>),
-    Primitive(fn(Vec<Value>) -> Result<Value, String>),
-    Closure(SharedCode, Env),
-    Macro(SharedCode, Env),
+    String(Rc<String>),
+    Char(char),
+    Cell(Rc<Pair>),
+    Vector(Rc<Vector<Value>>),
+    Bytevector(Rc<Vector<u8>>),
+    Primitive(fn(Vec<Value>) -> Result<Value, SchemeError>),
+    Native(Rc<Native>),
+    Closure(Rc<Lambda>, Env),
+    Macro(Rc<Lambda>, Env),
+    Syntax(Rc<SyntaxRules>),
+    Special(Special),
+    Continuation(Rc<Continuation>),
+    // エラーオブジェクト。メッセージとirritantsのリストを持つ。
+    Error(Rc<(Value, Value)>),
     Undefined,
 }
 
+/// ペア。carとcdrはset-car!などで書き換えられる。引用されたリストのような定数は
+/// 書き換えできないペアとして作る。
+#[derive(Debug)]
+pub struct Pair {
+    car: RefCell<Value>,
+    cdr: RefCell<Value>,
+    mutable: bool,
+}
+
+impl Pair {
+    pub fn new(car: Value, cdr: Value, mutable: bool) -> Pair {
+        Pair {
+            car: RefCell::new(car),
+            cdr: RefCell::new(cdr),
+            mutable,
+        }
+    }
+
+    pub fn car(&self) -> Value {
+        self.car.borrow().to_owned()
+    }
+
+    pub fn cdr(&self) -> Value {
+        self.cdr.borrow().to_owned()
+    }
+
+    pub fn is_mutable(&self) -> bool {
+        self.mutable
+    }
+
+    // 書き換えできないペアなら何もせずにfalseを返す。
+    pub fn set_car(&self, value: Value) -> bool {
+        if self.mutable {
+            *self.car.borrow_mut() = value;
+        }
+        self.mutable
+    }
+
+    pub fn set_cdr(&self, value: Value) -> bool {
+        if self.mutable {
+            *self.cdr.borrow_mut() = value;
+        }
+        self.mutable
+    }
+
+    // GC用。carとcdrをvisitに渡す。借用中で調べられなければfalse。
+    pub fn trace(&self, visit: &mut dyn FnMut(Child)) -> bool {
+        match (self.car.try_borrow(), self.cdr.try_borrow()) {
+            (Ok(car), Ok(cdr)) => {
+                visit(Child::Value(&car));
+                visit(Child::Value(&cdr));
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // GC用。到達できない循環を断ち切る。
+    pub fn clear(&self) {
+        *self.car.borrow_mut() = Value::Nil;
+        *self.cdr.borrow_mut() = Value::Nil;
+    }
+}
+
+/// ベクタとバイトベクタの中身。ペアと同じく、引用されたベクタのような定数は
+/// 書き換えできないものとして作る。
+#[derive(Debug)]
+pub struct Vector<T: Element> {
+    values: RefCell<Vec<T>>,
+    mutable: bool,
+}
+
+impl<T: Element> Vector<T> {
+    pub fn new(values: Vec<T>, mutable: bool) -> Vector<T> {
+        Vector {
+            values: RefCell::new(values),
+            mutable,
+        }
+    }
+
+    pub fn is_mutable(&self) -> bool {
+        self.mutable
+    }
+
+    pub fn borrow(&self) -> Ref<'_, Vec<T>> {
+        self.values.borrow()
+    }
+
+    pub fn try_borrow(&self) -> Result<Ref<'_, Vec<T>>, BorrowError> {
+        self.values.try_borrow()
+    }
+
+    // 書き換えできないベクタならNoneを返す。
+    pub fn borrow_mut(&self) -> Option<RefMut<'_, Vec<T>>> {
+        if self.mutable {
+            Some(self.values.borrow_mut())
+        } else {
+            None
+        }
+    }
+
+    // GC用。到達できない循環を断ち切る。
+    pub fn clear(&self) {
+        self.values.borrow_mut().clear();
+    }
+}
+
+// Pairと同じく、書き換えできるかどうかは比べない。
+impl<T: Element + PartialEq> PartialEq for Vector<T> {
+    fn eq(&self, other: &Vector<T>) -> bool {
+        *self.values.borrow() == *other.values.borrow()
+    }
+}
+
+/// ベクタの要素になる型。解放するときに、要素が持つオブジェクトの解放を後回しにする。
+pub trait Element: Sized {
+    fn defer_drop(values: &mut Vec<Self>, pending: &mut Vec<Garbage>);
+}
+
+impl Element for Value {
+    fn defer_drop(values: &mut Vec<Value>, pending: &mut Vec<Garbage>) {
+        for v in values.iter_mut() {
+            defer_drop(v, pending);
+        }
+    }
+}
+
+impl Element for u8 {
+    fn defer_drop(_: &mut Vec<u8>, _: &mut Vec<Garbage>) {}
+}
+
+// 長いリスト、深く入れ子になったリストやベクタ、長く連なった環境を解放するとき、既定の
+// dropでは要素の数だけ再帰してスタックを使い切る。他から参照されていないペア、ベクタ、
+// フレームの中身を取り出してpendingに積み、ループで解放する。取り出した後のオブジェクトは
+// 空なので、そのdropは再帰しない。
+pub enum Garbage {
+    Value(Value),
+    Frame(Rc<Frame>),
+}
+
+impl Drop for Pair {
+    fn drop(&mut self) {
+        let mut pending = Vec::new();
+        defer_drop(self.car.get_mut(), &mut pending);
+        defer_drop(self.cdr.get_mut(), &mut pending);
+        drop_pending(pending);
+    }
+}
+
+impl<T: Element> Drop for Vector<T> {
+    fn drop(&mut self) {
+        let mut pending = Vec::new();
+        T::defer_drop(self.values.get_mut(), &mut pending);
+        drop_pending(pending);
+    }
+}
+
+pub fn drop_pending(mut pending: Vec<Garbage>) {
+    while let Some(garbage) = pending.pop() {
+        match garbage {
+            Garbage::Value(Value::Cell(pair)) => {
+                if let Ok(mut pair) = Rc::try_unwrap(pair) {
+                    defer_drop(pair.car.get_mut(), &mut pending);
+                    defer_drop(pair.cdr.get_mut(), &mut pending);
+                }
+            }
+            Garbage::Value(Value::Vector(vector)) => {
+                if let Ok(mut vector) = Rc::try_unwrap(vector) {
+                    Value::defer_drop(vector.values.get_mut(), &mut pending);
+                }
+            }
+            Garbage::Frame(frame) => {
+                if let Ok(mut frame) = Rc::try_unwrap(frame) {
+                    frame.defer_drop(&mut pending);
+                }
+            }
+            Garbage::Value(_) => (),
+        }
+    }
+}
+
+pub fn defer_drop(value: &mut Value, pending: &mut Vec<Garbage>) {
+    let unique = match *value {
+        Value::Cell(ref pair) => Rc::strong_count(pair) == 1,
+        Value::Vector(ref vector) => Rc::strong_count(vector) == 1,
+        Value::Closure(_, ref mut env) |
+        Value::Macro(_, ref mut env) => return defer_drop_env(env, pending),
+        _ => false,
+    };
+    if unique {
+        pending.push(Garbage::Value(mem::replace(value, Value::Nil)));
+    }
+}
+
+pub fn defer_drop_env(env: &mut Env, pending: &mut Vec<Garbage>) {
+    if env.as_ref().is_some_and(|frame| Rc::strong_count(frame) == 1) {
+        if let Some(frame) = env.take() {
+            pending.push(Garbage::Frame(frame));
+        }
+    }
+}
+
+// 派生したValueのPartialEqと同じく中身を比べる。書き換えできるかどうかは比べない。
+impl PartialEq for Pair {
+    fn eq(&self, other: &Pair) -> bool {
+        *self.car.borrow() == *other.car.borrow() && *self.cdr.borrow() == *other.cdr.borrow()
+    }
+}
+
 impl Value {
-    pub fn to_ast(&self) -> Ast {
-        let string = format!("{}", self);
-        let (ast, _) = read(&string).unwrap();
-        ast[0].to_owned()
+    /// マクロの展開結果を式に戻す。手続きのように式として書けない値や、循環した構造はエラー。
+    pub fn to_ast(&self) -> Result<Ast, SchemeError> {
+        self.to_ast_helper(&mut HashSet::new(), 0)
+    }
+
+    // pathは変換中のペアとベクタ。循環していれば、その中のどれかに戻ってくる。
+    // depthはcarとベクタの要素の方向の入れ子の深さ。
+    fn to_ast_helper(&self, path: &mut HashSet<usize>, depth: usize) -> Result<Ast, SchemeError> {
+        if depth > MAX_SYNTAX_DEPTH {
+            return Err(SchemeError::syntax("syntax nested too deeply"));
+        }
+        match *self {
+            Value::Nil => Ok(Ast::Nil),
+            Value::Boolean(b) => Ok(Ast::Boolean(b)),
+            Value::Number(ref n) => Ok(Ast::Number(n.to_owned())),
+            Value::Symbol(ref s) => Ok(Ast::Symbol(s.to_owned())),
+            Value::String(ref s) => Ok(Ast::String(s.as_ref().to_owned())),
+            Value::Char(c) => Ok(Ast::Char(c)),
+            Value::Cell(_) => {
+                // cdrの方向は長くなりうるので、再帰せずにたどる。
+                let mut former = Vec::new();
+                let mut visited = Vec::new();
+                let mut list = self.to_owned();
+                let last = loop {
+                    let pair = match list {
+                        Value::Cell(pair) => pair,
+                        last => break last.to_ast_helper(path, depth),
+                    };
+                    let key = Rc::as_ptr(&pair) as usize;
+                    if !path.insert(key) {
+                        break Err(SchemeError::syntax("circular list cannot be used as syntax"));
+                    }
+                    visited.push(key);
+                    match pair.car().to_ast_helper(path, depth + 1) {
+                        Ok(ast) => former.push(ast),
+                        Err(e) => break Err(e),
+                    }
+                    list = pair.cdr();
+                };
+                for key in visited {
+                    path.remove(&key);
+                }
+                Ok(Ast::new_list(&former, last?))
+            }
+            Value::Vector(ref v) => {
+                let key = Rc::as_ptr(v) as usize;
+                if !path.insert(key) {
+                    return Err(SchemeError::syntax("circular vector cannot be used as syntax"));
+                }
+                let elems = v.borrow().iter().map(|x| x.to_ast_helper(path, depth + 1)).collect::<Result<_, _>>();
+                path.remove(&key);
+                elems.map(Ast::Vector)
+            }
+            Value::Bytevector(ref bytes) => Ok(Ast::Bytevector(bytes.borrow().to_owned())),
+            Value::Undefined => Ok(Ast::Undefined),
+            ref value => Err(SchemeError::syntax(&format!("cannot be used as syntax: {}", value))),
+        }
     }
 
     pub fn cons(car: Value, cdr: Value) -> Value {
-        Value::Cell(Rc::new((car, cdr)))
+        let pair = Rc::new(Pair::new(car, cdr, true));
+        gc::register_pair(&pair);
+        Value::Cell(pair)
+    }
+
+    pub fn integer(i: i64) -> Value {
+        Value::Number(Number::from(i))
+    }
+
+    pub fn string(s: &str) -> Value {
+        let s = Rc::new(s.to_owned());
+        gc::register_string(&s);
+        Value::String(s)
+    }
+
+    pub fn vector(values: Vec<Value>) -> Value {
+        let vector = Rc::new(Vector::new(values, true));
+        gc::register_vector(&vector);
+        Value::Vector(vector)
+    }
+
+    pub fn bytevector(bytes: Vec<u8>) -> Value {
+        let bytes = Rc::new(Vector::new(bytes, true));
+        gc::register_bytevector(&bytes);
+        Value::Bytevector(bytes)
+    }
+
+    pub fn native<F>(name: &str, arity: Arity, f: F) -> Value
+        where F: Fn(Vec<Value>) -> Result<Value, SchemeError> + 'static
+    {
+        Value::Native(Rc::new(Native::new(name, arity, f)))
+    }
+
+    pub fn error(message: Value, irritants: Value) -> Value {
+        Value::Error(Rc::new((message, irritants)))
     }
 
     pub fn car(&self) -> Option<Value> {
         match *self {
-            Value::Cell(ref cell) => Some(cell.0.to_owned()),
+            Value::Cell(ref cell) => Some(cell.car()),
             _ => None,
         }
     }
 
     pub fn cdr(&self) -> Option<Value> {
         match *self {
-            Value::Cell(ref cell) => Some(cell.1.to_owned()),
+            Value::Cell(ref cell) => Some(cell.cdr()),
             _ => None,
         }
     }
 }
 
+// eq?、eqv?、equal?による比較。派生したPartialEqはリストやクロージャを構造的に比べるので、
+// Schemeの同一性の判定には使わない。
+impl Value {
+    /// eq?。ペアや手続きのように参照で表される値は、同じオブジェクトかどうかを比べる。
+    pub fn is_eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) |
+            (Value::Undefined, Value::Undefined) => true,
+            (Value::Boolean(a), Value::Boolean(b)) => a == b,
+            (Value::Symbol(a), Value::Symbol(b)) => a == b,
+            (Value::Char(a), Value::Char(b)) => a == b,
+            // 固定長の整数は値が同じなら同じオブジェクトとみなす。
+            (Value::Number(Number::Integer(a)), Value::Number(Number::Integer(b))) => a == b,
+            (Value::String(a), Value::String(b)) => Rc::ptr_eq(a, b),
+            (Value::Cell(a), Value::Cell(b)) => Rc::ptr_eq(a, b),
+            (Value::Vector(a), Value::Vector(b)) => Rc::ptr_eq(a, b),
+            (Value::Bytevector(a), Value::Bytevector(b)) => Rc::ptr_eq(a, b),
+            (Value::Primitive(a), Value::Primitive(b)) => *a as usize == *b as usize,
+            (Value::Native(a), Value::Native(b)) => Rc::ptr_eq(a, b),
+            (Value::Closure(a, a_env), Value::Closure(b, b_env)) |
+            (Value::Macro(a, a_env), Value::Macro(b, b_env)) => {
+                Rc::ptr_eq(a, b) && env_eq(a_env, b_env)
+            }
+            (Value::Syntax(a), Value::Syntax(b)) => Rc::ptr_eq(a, b),
+            (Value::Special(a), Value::Special(b)) => a == b,
+            (Value::Continuation(a), Value::Continuation(b)) => Rc::ptr_eq(a, b),
+            (Value::Error(a), Value::Error(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+
+    /// eqv?。eq?に加えて、正確さと値の等しい数を同じものとする。
+    /// 不正確な数はビット列で比べるので、0.0と-0.0は区別し、NaN同士は等しい。
+    pub fn is_eqv(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Number(Number::Real(a)), Value::Number(Number::Real(b))) => {
+                a.to_bits() == b.to_bits()
+            }
+            (Value::Number(a), Value::Number(b)) => a == b,
+            _ => self.is_eq(other),
+        }
+    }
+
+    /// equal?。ペア、ベクタ、文字列、バイトベクタを中身で比べる。
+    /// 比較中のペアやベクタの組を覚えておき、循環した構造でも停止する。
+    pub fn is_equal(&self, other: &Value) -> bool {
+        let mut visited = HashSet::new();
+        let mut pending = vec![(self.to_owned(), other.to_owned())];
+        while let Some((a, b)) = pending.pop() {
+            match (&a, &b) {
+                (Value::Cell(x), Value::Cell(y)) => {
+                    let key = (Rc::as_ptr(x) as usize, Rc::as_ptr(y) as usize);
+                    if Rc::ptr_eq(x, y) || !visited.insert(key) {
+                        continue;
+                    }
+                    pending.push((x.cdr(), y.cdr()));
+                    pending.push((x.car(), y.car()));
+                }
+                (Value::Vector(x), Value::Vector(y)) => {
+                    let key = (Rc::as_ptr(x) as usize, Rc::as_ptr(y) as usize);
+                    if Rc::ptr_eq(x, y) || !visited.insert(key) {
+                        continue;
+                    }
+                    let (x, y) = (x.borrow(), y.borrow());
+                    if x.len() != y.len() {
+                        return false;
+                    }
+                    pending.extend(x.iter().cloned().zip(y.iter().cloned()).rev());
+                }
+                (Value::String(x), Value::String(y)) => {
+                    if x != y {
+                        return false;
+                    }
+                }
+                (Value::Bytevector(x), Value::Bytevector(y)) => {
+                    if *x.borrow() != *y.borrow() {
+                        return false;
+                    }
+                }
+                _ => {
+                    if !a.is_eqv(&b) {
+                        return false;
+                    }
+                }
+            }
+        }
+        true
+    }
+}
+
+fn env_eq(a: &Env, b: &Env) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => Rc::ptr_eq(a, b),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        print(f, self)
+        print(f, self, true, &mut HashSet::new(), 0)
+    }
+}
+
+// displayによる表示。writeと異なり文字列や文字をそのまま出力する。
+pub struct DisplayValue<'a>(&'a Value);
+
+impl<'a> fmt::Display for DisplayValue<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        print(f, self.0, false, &mut HashSet::new(), 0)
     }
 }
 
-fn print(f: &mut fmt::Formatter, val: &Value) -> fmt::Result {
+impl Value {
+    pub fn display(&self) -> DisplayValue<'_> {
+        DisplayValue(self)
+    }
+}
+
+// pathは表示中のペアとベクタ。循環していればその中のどれかに戻ってくるので、
+// そこは...と表示する。depthはcarとベクタの要素の方向の入れ子の深さ。
+fn print(f: &mut fmt::Formatter,
+         val: &Value,
+         write: bool,
+         path: &mut HashSet<usize>,
+         depth: usize)
+         -> fmt::Result {
+    if depth > MAX_PRINT_DEPTH {
+        return write!(f, "...");
+    }
     match *val {
         Value::Nil => write!(f, "()"),
         Value::Boolean(ref b) => write!(f, "{}", if *b { "#t" } else { "#f" }),
-        Value::Integer(ref i) => write!(f, "{}", i),
+        Value::Number(ref n) => write!(f, "{}", n),
         Value::Symbol(ref s) => write!(f, "{}", s),
+        Value::String(ref s) if write => write_string(f, s),
+        Value::String(ref s) => write!(f, "{}", s),
+        Value::Char(c) if write => write_char(f, c),
+        Value::Char(c) => write!(f, "{}", c),
+        Value::Cell(ref cell) if path.contains(&(Rc::as_ptr(cell) as usize)) => write!(f, "..."),
         Value::Cell(ref cell) => {
-            try!(write!(f, "("));
-            try!(print_cell(f, cell));
+            write!(f, "(")?;
+            print_cell(f, cell, write, path, depth)?;
+            write!(f, ")")
+        }
+        Value::Vector(ref values) => {
+            let key = Rc::as_ptr(values) as usize;
+            if !path.insert(key) {
+                return write!(f, "...");
+            }
+            write!(f, "#(")?;
+            for (i, v) in values.borrow().iter().enumerate() {
+                if i > 0 {
+                    write!(f, " ")?;
+                }
+                print(f, v, write, path, depth + 1)?;
+            }
+            path.remove(&key);
+            write!(f, ")")
+        }
+        Value::Bytevector(ref bytes) => {
+            write!(f, "#u8(")?;
+            for (i, b) in bytes.borrow().iter().enumerate() {
+                if i > 0 {
+                    write!(f, " ")?;
+                }
+                write!(f, "{}", b)?;
+            }
             write!(f, ")")
         }
         Value::Primitive(_) => write!(f, "#<subr>"),
-        Value::Closure(_, _) => write!(f, "#<closure>"),
+        Value::Native(ref native) => write!(f, "#<subr {}>", native.name()),
+        Value::Closure(ref lambda, _) => {
+            match lambda.name {
+                Some(ref name) => write!(f, "#<closure {}>", name),
+                None => write!(f, "#<closure>"),
+            }
+        }
         Value::Macro(_, _) => write!(f, "#<macro>"),
+        Value::Syntax(_) => write!(f, "#<syntax>"),
+        Value::Special(_) => write!(f, "#<subr>"),
+        Value::Continuation(_) => write!(f, "#<continuation>"),
+        Value::Error(ref error) => {
+            write!(f, "#<error ")?;
+            print(f, &error.0, write, path, depth + 1)?;
+            if let Value::Cell(ref irritants) = error.1 {
+                write!(f, " ")?;
+                print_cell(f, irritants, write, path, depth)?;
+            }
+            write!(f, ">")
+        }
         Value::Undefined => write!(f, "#<undefined>"),
     }
 }
 
-fn print_cell(f: &mut fmt::Formatter, pair: &Rc<(Value, Value)>) -> fmt::Result {
-    try!(print(f, &pair.0));
-    match pair.1 {
-        Value::Nil => Ok(()),
-        Value::Cell(ref cdr) => {
-            try!(write!(f, " "));
-            print_cell(f, cdr)
+// cdrの方向は長くなりうるので、再帰せずにたどる。
+fn print_cell(f: &mut fmt::Formatter,
+              pair: &Pair,
+              write: bool,
+              path: &mut HashSet<usize>,
+              depth: usize)
+              -> fmt::Result {
+    let mut visited = vec![pair as *const Pair as usize];
+    path.insert(visited[0]);
+    print(f, &pair.car(), write, path, depth + 1)?;
+    let mut rest = pair.cdr();
+    loop {
+        rest = match rest {
+            Value::Nil => break,
+            Value::Cell(ref next) if path.contains(&(Rc::as_ptr(next) as usize)) => {
+                write!(f, " ...")?;
+                break;
+            }
+            Value::Cell(next) => {
+                write!(f, " ")?;
+                path.insert(Rc::as_ptr(&next) as usize);
+                visited.push(Rc::as_ptr(&next) as usize);
+                print(f, &next.car(), write, path, depth + 1)?;
+                next.cdr()
+            }
+            ref v => {
+                write!(f, " . ")?;
+                print(f, v, write, path, depth + 1)?;
+                break;
+            }
+        };
+    }
+    for key in visited {
+        path.remove(&key);
+    }
+    Ok(())
+}
+
+fn write_string(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\t' => write!(f, "\\t")?,
+            '\r' => write!(f, "\\r")?,
+            c if c.is_control() => write!(f, "\\x{:x};", c as u32)?,
+            c => write!(f, "{}", c)?,
         }
-        ref v => {
-            try!(write!(f, " . "));
-            print(f, v)
+    }
+    write!(f, "\"")
+}
+
+fn write_char(f: &mut fmt::Formatter, c: char) -> fmt::Result {
+    match char_name(c) {
+        Some(name) => write!(f, "#\\{}", name),
+        None if c.is_control() => write!(f, "#\\x{:x}", c as u32),
+        None => write!(f, "#\\{}", c),
+    }
+}
+
+// #\spaceのような名前付きの文字。readerもこの対応を使う。
+pub const CHAR_NAMES: [(&str, char); 10] = [("alarm", '\x07'),
+                                             ("backspace", '\x08'),
+                                             ("delete", '\x7f'),
+                                             ("escape", '\x1b'),
+                                             ("newline", '\n'),
+                                             ("null", '\0'),
+                                             ("return", '\r'),
+                                             ("space", ' '),
+                                             ("tab", '\t'),
+                                             ("nul", '\0')];
+
+fn char_name(c: char) -> Option<&'static str> {
+    CHAR_NAMES
+        .iter()
+        .find(|&&(_, ch)| ch == c)
+        .map(|&(name, _)| name)
+}
+
+// リストを先頭からたどりながら、Floydの方法で循環を見つける。
+// slowはたどっている位置の半分の速さで進み、循環していればいずれ追いつかれる。
+pub struct CycleCheck {
+    slow: Value,
+    odd: bool,
+}
+
+impl CycleCheck {
+    pub fn new(list: &Value) -> CycleCheck {
+        CycleCheck {
+            slow: list.to_owned(),
+            odd: false,
         }
     }
+
+    // たどっている位置をlistに1つ進めるたびに呼ぶ。循環していればtrue。
+    pub fn step(&mut self, list: &Value) -> bool {
+        if self.odd {
+            self.slow = self.slow.cdr().unwrap_or(Value::Nil);
+        }
+        self.odd = !self.odd;
+        match (list, &self.slow) {
+            (Value::Cell(fast), Value::Cell(slow)) => Rc::ptr_eq(fast, slow),
+            _ => false,
+        }
+    }
+}
+
+// 書き換えできないペアでできた定数のリスト。
+pub fn vec2constant(former: &[Value], last: Value) -> Value {
+    former
+        .iter()
+        .rev()
+        .fold(last, |cdr, car| Value::Cell(Rc::new(Pair::new(car.to_owned(), cdr, false))))
 }
 
+// 長いリストでもスタックを使わないよう、末尾から順に組み立てる。
 pub fn vec2cons(former: &[Value], last: Value) -> Value {
-    if former.is_empty() {
-        last
-    } else {
-        Value::cons(former[0].to_owned(), vec2cons(&former[1..], last))
-    }
+    former
+        .iter()
+        .rev()
+        .fold(last, |cdr, car| Value::cons(car.to_owned(), cdr))
 }
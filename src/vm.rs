@@ -1,172 +1,756 @@
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
 use std::{fmt, mem};
+use std::ops::Deref;
 use std::rc::Rc;
-use compiler::Ast;
-use value::{Value, vec2cons};
+use span::Span;
+use syntax::SyntaxRules;
+use error::{ErrorKind, SchemeError};
+use gc::{self, Child, Heap};
+use limits::Budget;
+use native::Arity;
+use value::{Element, Garbage, Value, defer_drop_env, drop_pending, vec2cons};
 
-pub type Global = HashMap<String, Value>;
+// グローバル変数の表。名前はコンパイル時にスロットの番号に変換され、実行時のLdgなどは
+// 名前を引かずにスロットを直接読み書きする。未定義の変数のスロットはNoneを持つ。
+// コンパイル中のマクロの展開を含め、評価の間はどこからでも参照されるので、
+// 資源の使用量もここで数える。
+#[derive(Debug, Clone, Default)]
+pub struct Global {
+    slots: Vec<Option<Value>>,
+    names: Vec<String>,
+    index: HashMap<String, usize>,
+    budget: Budget,
+    heap: Rc<Heap>,
+}
+
+impl Global {
+    pub fn new() -> Global {
+        Global::default()
+    }
+
+    // nameのスロットの番号を返す。初めて現れた名前には未定義のスロットを割り当てる。
+    pub fn slot(&mut self, name: &str) -> usize {
+        if let Some(&slot) = self.index.get(name) {
+            return slot;
+        }
+        let slot = self.slots.len();
+        self.slots.push(None);
+        self.names.push(name.to_owned());
+        self.index.insert(name.to_owned(), slot);
+        slot
+    }
+
+    pub fn name(&self, slot: usize) -> &str {
+        self.names.get(slot).map_or("#<unknown>", String::as_str)
+    }
+
+    pub fn load(&self, slot: usize) -> Option<&Value> {
+        self.slots.get(slot).and_then(Option::as_ref)
+    }
+
+    pub fn store(&mut self, slot: usize, value: Value) {
+        self.slots[slot] = Some(value);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.index.get(name).and_then(|&slot| self.load(slot))
+    }
+
+    pub fn insert(&mut self, name: &str, value: Value) {
+        let slot = self.slot(name);
+        self.store(slot, value);
+    }
+
+    // 資源の使用量を入れ替え、それまでのものを返す。評価を始めるときや、
+    // 中断した評価を再開するときに使う。
+    pub fn replace_budget(&mut self, mut budget: Budget) -> Budget {
+        budget.resume();
+        self.heap.set_limit(budget.heap_limit());
+        let mut previous = mem::replace(&mut self.budget, budget);
+        previous.pause();
+        previous
+    }
+
+    // コンパイルやマクロの展開で式を1つ処理するたびに呼び、命令1つとして資源を数える。
+    pub fn spend(&mut self) -> Result<(), SchemeError> {
+        self.budget.spend_expansion(&self.heap)
+    }
+
+    // 循環した構造の登録簿。実行の間は現在のヒープになる。
+    pub fn heap(&self) -> &Heap {
+        &self.heap
+    }
+
+    // 値が束縛されている変数の名前。
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.names
+            .iter()
+            .zip(&self.slots)
+            .filter(|&(_, value)| value.is_some())
+            .map(|(name, _)| name.as_str())
+    }
+}
+
+/// 命令の数を限って実行した結果。
+pub enum Status {
+    Done(Value),
+    // 命令の数を使い切った。
+    Paused,
+    // プリミティブがホストに制御を返すよう求めた。値はホストに渡す。
+    Yielded(Value),
+}
 
 pub struct Machine {
     stack: Stack,
     env: Env,
     code: (SharedCode, CodePos),
     dump: Dump,
+    // 実行中のクロージャ。トップレベルではNone。バックトレースに使う。
+    procedure: Procedure,
 }
 
 type Stack = Vec<Value>;
-pub type SharedCode = Rc<Box<[CodeOp]>>;
-pub type MutableCode = Vec<CodeOp>;
+pub type SharedCode = Rc<Code>;
 // 次に実行するCodeOpを指すインデックス。
 type CodePos = usize;
-pub type Env = Vec<Vec<Value>>;
+// 環境は内側から外側へとつながるフレームの連結リスト。クロージャの生成や
+// 関数適用はRcの複製と新しいフレームの追加だけで済み、ネストの深さに依存しない。
+pub type Env = Option<Rc<Frame>>;
+
+// フレームはクロージャ間で共有され、set!による変更は全てのクロージャから見える。
+#[derive(Debug, PartialEq)]
+pub struct Frame {
+    values: RefCell<Vec<Value>>,
+    parent: Env,
+    // ヒープに登録済みならtrue。登録済みのフレームの外側のフレームも登録済みになる。
+    registered: Cell<bool>,
+}
 type Dump = Vec<DumpOp>;
+type Procedure = Option<Rc<Lambda>>;
+
+// エラーのバックトレースに並べる手続きの数の上限。
+const MAX_BACKTRACE: usize = 20;
+
+fn same_procedure(a: &Procedure, b: &Procedure) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => Rc::ptr_eq(a, b),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+// lambda式をコンパイルしたもの。名前は(define (f ...) ...)などから分かる場合に付け、
+// エラーのバックトレースに使う。arityは仮引数の数で、可変長引数があればAtLeastになる。
+#[derive(Debug, PartialEq)]
+pub struct Lambda {
+    pub code: SharedCode,
+    pub name: Option<String>,
+    pub arity: Arity,
+}
+
+impl Lambda {
+    // エラーメッセージやバックトレースで手続きを示す名前。
+    pub fn label(&self) -> String {
+        self.name.to_owned().unwrap_or_else(|| "#<lambda>".to_owned())
+    }
+}
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum CodeOp {
     Ld(Location),
-    Ldc(Ast),
-    Ldg(String),
-    Ldf(SharedCode),
+    Set(Location),
+    // コンパイル時に作った定数。実行のたびに同じ値を積むので、引用されたリストはeq?になる。
+    Ldc(Value),
+    // グローバル変数のスロットの番号。
+    Ldg(usize),
+    Setg(usize),
+    Ldf(Rc<Lambda>),
+    // 現在のフレームのn番目以降の引数をリストにまとめる。可変長引数を取るlambdaの先頭で実行する。
+    Rest(usize),
     App(usize),
+    // 末尾位置の関数適用。現在のフレームを再利用するためDumpAppを積まない。
+    TApp(usize),
     Rtn,
     Sel(SharedCode, SharedCode),
+    // 末尾位置のSel。分岐先のコードはJoinではなくRtnで終わるのでDumpSelを積まない。
+    TSel(SharedCode, SharedCode),
     Join,
-    Def(String),
+    Def(usize),
     Defm(String),
+    Defs(String, Rc<SyntaxRules>),
     Pop,
+    // 例外ハンドラの呼び出しから戻る際に、DumpHandlerを取り除く。
+    PopHandler,
+    // raiseで呼ばれたハンドラが戻ってきた場合に二次的な例外を起こす。
+    HandlerReturned,
 }
 
-pub type Location = (usize, Position);
+// 何番目に外側のフレームか(0が最も内側)と、フレーム内の位置。
+pub type Location = (usize, usize);
 
+// スタックやダンプを直接操作する必要があるため、VMが自ら実装する手続き。
 #[derive(Debug, Clone, Copy, PartialEq)]
-pub enum Position {
-    Index(usize),
-    Rest(usize),
+pub enum Special {
+    CallCC,
+    Raise,
+    RaiseContinuable,
+    WithExceptionHandler,
+    Error,
 }
 
+// call/ccで捕捉した継続。Machineの制御状態をそのまま保存する。
+#[derive(Debug, Clone, PartialEq)]
+pub struct Continuation {
+    stack: Stack,
+    env: Env,
+    code: (SharedCode, CodePos),
+    dump: Dump,
+    procedure: Procedure,
+}
+
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, Clone, PartialEq)]
 enum DumpOp {
-    DumpApp(Stack, Env, (SharedCode, CodePos)),
+    DumpApp(Stack, Env, (SharedCode, CodePos), Procedure),
     DumpSel((SharedCode, CodePos)),
+    // その時点で有効な例外ハンドラのスタック。最後の要素が最も内側のハンドラ。
+    DumpHandler(Vec<Value>),
+}
+
+// コンパイル済みのコードと、各CodeOpがどの式から生成されたかを表すソースマップ。
+// spansはopsと同じ長さで、位置の分からない式から生成されたCodeOpはNoneになる。
+#[derive(Debug, PartialEq)]
+pub struct Code {
+    ops: Box<[CodeOp]>,
+    spans: Box<[Option<Span>]>,
+}
+
+impl Code {
+    pub fn span(&self, pos: CodePos) -> Option<&Span> {
+        self.spans.get(pos).and_then(Option::as_ref)
+    }
+}
+
+impl Deref for Code {
+    type Target = [CodeOp];
+
+    fn deref(&self) -> &[CodeOp] {
+        &self.ops
+    }
+}
+
+impl From<Vec<CodeOp>> for Code {
+    fn from(ops: Vec<CodeOp>) -> Code {
+        let spans = vec![None; ops.len()].into_boxed_slice();
+        Code {
+            ops: ops.into_boxed_slice(),
+            spans,
+        }
+    }
+}
+
+// コンパイル中のコード。pushしたCodeOpには、その時点でコンパイルしている式の位置を記録する。
+#[derive(Debug, Default)]
+pub struct MutableCode {
+    ops: Vec<CodeOp>,
+    spans: Vec<Option<Span>>,
+    span: Option<Span>,
+    // コンパイル中のリストの入れ子の深さ。
+    depth: usize,
+}
+
+impl MutableCode {
+    pub fn new() -> MutableCode {
+        MutableCode::default()
+    }
+
+    // lambdaの本体やifの分岐先のように、別に組み立てるコード。外側の式の位置を引き継ぐ。
+    pub fn nested(&self, first: CodeOp) -> MutableCode {
+        let mut code = MutableCode {
+            span: self.span.to_owned(),
+            depth: self.depth,
+            ..MutableCode::default()
+        };
+        code.push(first);
+        code
+    }
+
+    pub fn push(&mut self, op: CodeOp) {
+        self.ops.push(op);
+        self.spans.push(self.span.to_owned());
+    }
+
+    pub fn pop(&mut self) -> Option<CodeOp> {
+        self.spans.pop();
+        self.ops.pop()
+    }
+
+    // 以降にpushするCodeOpの位置をspanにし、元の位置を返す。spanがNoneなら外側の位置のまま。
+    pub fn enter(&mut self, span: Option<&Span>) -> Option<Span> {
+        self.depth += 1;
+        match span {
+            Some(span) => self.span.replace(span.to_owned()),
+            None => self.span.to_owned(),
+        }
+    }
+
+    pub fn leave(&mut self, span: Option<Span>) {
+        self.depth -= 1;
+        self.span = span;
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn into_shared(self) -> SharedCode {
+        Rc::new(Code {
+                    ops: self.ops.into_boxed_slice(),
+                    spans: self.spans.into_boxed_slice(),
+                })
+    }
 }
 
 impl Machine {
-    pub fn run(env: Env, code: SharedCode, global: &mut Global) -> Result<Value, String> {
-        let clen = code.len();
-        let mut machine = Machine {
-            stack: Vec::new(),
-            env: env,
-            code: (code, clen - 1),
+    pub fn run(env: Env, code: SharedCode, global: &mut Global) -> Result<Value, SchemeError> {
+        Machine::new(Vec::new(), env, code).execute(global)
+    }
+
+    /// `f`を`args`に適用した結果を返す。マクロ展開のようにRustの側から手続きを呼ぶ場合に使う。
+    pub fn apply(f: Value, args: Vec<Value>, global: &mut Global) -> Result<Value, SchemeError> {
+        let n = args.len();
+        let mut stack = args;
+        stack.push(f);
+        let code = Rc::new(Code::from(vec![CodeOp::App(n)]));
+        Machine::new(stack, None, code).execute(global)
+    }
+
+    /// 中断と再開のできる実行を用意する。命令は`resume`で実行する。
+    pub fn spawn(code: SharedCode) -> Machine {
+        Machine::new(Vec::new(), None, code)
+    }
+
+    /// 最大で`steps`個の命令を実行し、実行した数だけ`steps`を減らす。
+    pub fn resume(&mut self, global: &mut Global, steps: &mut u64) -> Result<Status, SchemeError> {
+        self.run_for(global, Some(steps))
+    }
+
+    /// yieldで中断したプリミティブの戻り値を渡す。続きはその値を受け取ったところから実行する。
+    pub fn push_result(&mut self, value: Value) {
+        self.stack.push(value);
+    }
+
+    fn new(stack: Stack, env: Env, code: SharedCode) -> Machine {
+        // 空のコードはwrapping_subで最初から終わった状態になる。
+        let pos = code.len().wrapping_sub(1);
+        Machine {
+            stack,
+            env,
+            code: (code, pos),
             dump: Vec::new(),
-        };
-        while machine.code.1 < ::std::usize::MAX {
-            // machine.code.0はRc<Box<[CodeOp]>>なのでclone()は軽量な処理。
-            let op = &machine.code.0.clone()[machine.code.1];
+            procedure: None,
+        }
+    }
+
+    fn execute(mut self, global: &mut Global) -> Result<Value, SchemeError> {
+        match self.run_for(global, None)? {
+            Status::Done(value) => Ok(value),
+            // 中断できない実行では、プリミティブが中断を求めるとエラーになる。
+            Status::Yielded(value) => {
+                let mut error = SchemeError::new(ErrorKind::Yield(value));
+                error.backtrace = self.backtrace();
+                Err(error)
+            }
+            Status::Paused => Err(SchemeError::runtime("Runtime error: Paused")),
+        }
+    }
+
+    // stepsがNoneなら終わるまで実行する。
+    fn run_for(&mut self, global: &mut Global, mut steps: Option<&mut u64>) -> Result<Status, SchemeError> {
+        // 実行中に作られたオブジェクトは、このGlobalを持つインタプリタのヒープに登録する。
+        let _heap = gc::enter(&global.heap);
+        while self.code.1 < usize::MAX {
+            if let Some(ref mut steps) = steps {
+                if **steps == 0 {
+                    return Ok(Status::Paused);
+                }
+                **steps -= 1;
+            }
+            // 命令の合間はRefCellの借用が残っていないので、循環の回収を行える。
+            if global.heap.should_collect() {
+                global.heap.collect();
+            }
+            // self.code.0はRc<Code>なのでclone()は軽量な処理。
+            let (code, pos) = (self.code.0.clone(), self.code.1);
+            let op = &code[pos];
             // usizeにはマイナス値がないのでwrapping_sub()を使う。
             // code.1が0の時にwrapping_sum(1)を実行するとusize::MAXになる。
-            machine.code.1 = machine.code.1.wrapping_sub(1);
-            machine.tick(op, global)?;
+            self.code.1 = self.code.1.wrapping_sub(1);
+            // 命令を実行した後に資源を数えるので、最後の命令で確保したものも上限を確かめる。
+            let result = self.tick(op, global).and_then(|_| global.budget.spend(self.dump.len(), &global.heap));
+            if let Err(mut error) = result {
+                // 中断を求めたプリミティブの戻り値は、再開するときにpush_resultで積まれる。
+                if let ErrorKind::Yield(ref value) = *error.kind {
+                    return Ok(Status::Yielded(value.to_owned()));
+                }
+                // ハンドラがなければ、その時点の呼び出しの連鎖をバックトレースとして返す。
+                // 資源の上限はハンドラで捕捉できると制限にならないので、常にそのまま返す。
+                let limit = matches!(*error.kind, ErrorKind::LimitExceeded(_));
+                if limit || self.handlers().is_empty() {
+                    if error.span.is_none() {
+                        error.span = code.span(pos).cloned();
+                    }
+                    error.backtrace = self.backtrace();
+                    return Err(error);
+                }
+                // VMやプリミティブのエラーもエラーオブジェクトとして例外ハンドラに渡す。
+                self.raise(error.to_object(), false, false)?;
+            }
         }
-        match machine.stack.pop() {
-            Some(v) => return Ok(v),
-            None => return Ok(Value::Undefined),
+        match self.stack.pop() {
+            Some(v) => Ok(Status::Done(v)),
+            None => Ok(Status::Done(Value::Undefined)),
         }
     }
 
-    fn tick(&mut self, op: &CodeOp, global: &mut Global) -> Result<(), String> {
-        match op {
-            &CodeOp::Ld(location) => {
+    fn tick(&mut self, op: &CodeOp, global: &mut Global) -> Result<(), SchemeError> {
+        match *op {
+            CodeOp::Ld(location) => {
                 let value = get_var(&self.env, location)
-                    .ok_or_else(|| "Runtime error: Ld")?;
+                    .ok_or("Runtime error: Ld")?;
                 self.stack.push(value);
                 Ok(())
             }
-            &CodeOp::Ldc(ref ast) => Ok(self.stack.push(ast.to_value())),
-            &CodeOp::Ldg(ref name) => {
+            CodeOp::Set(location) => {
+                let value = self.stack.pop().ok_or("Runtime error: Set")?;
+                set_var(&self.env, location, value).ok_or("Runtime error: Set")?;
+                self.stack.push(Value::Undefined);
+                Ok(())
+            }
+            CodeOp::Ldc(ref value) => {
+                self.stack.push(value.to_owned());
+                Ok(())
+            }
+            CodeOp::Ldg(slot) => {
                 let value = global
-                    .get(name)
-                    .ok_or_else(|| format!("unbound variable: {}", name))?;
+                    .load(slot)
+                    .ok_or_else(|| SchemeError::unbound_variable(global.name(slot)))?;
                 self.stack.push(value.to_owned());
                 Ok(())
             }
-            &CodeOp::Ldf(ref code) => {
+            CodeOp::Setg(slot) => {
+                let value = self.stack.pop().ok_or("Runtime error: Setg")?;
+                if global.load(slot).is_none() {
+                    return Err(SchemeError::unbound_variable(global.name(slot)));
+                }
+                global.store(slot, value);
+                self.stack.push(Value::Undefined);
+                Ok(())
+            }
+            CodeOp::Ldf(ref lambda) => {
+                Frame::register(&self.env);
                 self.stack
-                    .push(Value::Closure(code.clone(), self.env.to_owned()));
+                    .push(Value::Closure(lambda.clone(), self.env.to_owned()));
                 Ok(())
             }
-            &CodeOp::App(i) => {
-                let n = self.stack.len() - 1;
-                if i > n {
-                    return Err("Runtime error: App".to_owned());
-                }
-                match self.stack.pop() {
-                    Some(Value::Closure(code, mut env)) => {
-                        env.push(self.stack.split_off(n - i));
-                        let prev_stack = mem::replace(&mut self.stack, Vec::new());
-                        let prev_env = mem::replace(&mut self.env, env);
-                        let clen = code.len();
-                        let prev_code = mem::replace(&mut self.code, (code, clen - 1));
-
-                        self.dump
-                            .push(DumpOp::DumpApp(prev_stack, prev_env, prev_code));
-                        Ok(())
-                    }
-                    Some(Value::Primitive(procedure)) => {
-                        let result = (procedure)(self.stack.split_off(n - i))?;
-                        self.stack.push(result);
-                        Ok(())
-                    }
-                    _ => Err("Runtime error: App".to_owned()),
+            CodeOp::Rest(index) => {
+                let frame = self.env.as_ref().ok_or("Runtime error: Rest")?;
+                let mut frame = frame.values.borrow_mut();
+                if index > frame.len() {
+                    return Err(SchemeError::runtime("Runtime error: Rest"));
                 }
+                let rest = frame.split_off(index);
+                frame.push(vec2cons(&rest, Value::Nil));
+                Ok(())
             }
-            &CodeOp::Rtn => {
-                if let (Some(s), Some(DumpOp::DumpApp(mut stack, env, code))) =
+            CodeOp::App(i) => self.app(i, false),
+            CodeOp::TApp(i) => self.app(i, true),
+            CodeOp::Rtn => {
+                if let (Some(s), Some(DumpOp::DumpApp(mut stack, env, code, procedure))) =
                     (self.stack.pop(), self.dump.pop()) {
                     stack.push(s);
                     self.stack = stack;
                     self.env = env;
                     self.code = code;
+                    self.procedure = procedure;
                     Ok(())
                 } else {
-                    Err("Runtime error: Rtn".to_owned())
+                    Err(SchemeError::runtime("Runtime error: Rtn"))
                 }
             }
-            &CodeOp::Sel(ref conseq, ref alt) => {
-                let value = self.stack.pop().ok_or_else(|| "Runtime error: Sel")?;
-                let code = if value == Value::Boolean(false) {
-                    alt
-                } else {
-                    conseq
-                };
+            CodeOp::Sel(ref conseq, ref alt) => {
+                let code = self.select(conseq, alt)?;
                 let prev_code = mem::replace(&mut self.code, (code.clone(), code.len() - 1));
                 self.dump.push(DumpOp::DumpSel(prev_code));
                 Ok(())
             }
-            &CodeOp::Join => {
+            CodeOp::TSel(ref conseq, ref alt) => {
+                let code = self.select(conseq, alt)?;
+                self.code = (code.clone(), code.len() - 1);
+                Ok(())
+            }
+            CodeOp::Join => {
                 if let Some(DumpOp::DumpSel(code)) = self.dump.pop() {
                     self.code = code;
                     Ok(())
                 } else {
-                    Err("Runtime error: Join".to_owned())
+                    Err(SchemeError::runtime("Runtime error: Join"))
                 }
             }
-            &CodeOp::Def(ref name) => {
-                let value = self.stack.pop().ok_or_else(|| "Runtime error: Def")?;
-                global.insert(name.to_owned(), value);
+            CodeOp::Def(slot) => {
+                let value = self.stack.pop().ok_or("Runtime error: Def")?;
+                global.store(slot, value);
                 Ok(())
             }
-            &CodeOp::Defm(ref name) => {
-                if let Some(Value::Closure(code, env)) = self.stack.pop() {
-                    global.insert(name.to_owned(), Value::Macro(code, env));
+            CodeOp::Defm(ref name) => {
+                if let Some(Value::Closure(lambda, env)) = self.stack.pop() {
+                    global.insert(name, Value::Macro(lambda, env));
                     Ok(())
                 } else {
-                    unimplemented!()
+                    Err(SchemeError::syntax("define-macro requires a procedure"))
                 }
             }
-            &CodeOp::Pop => {
+            CodeOp::Defs(ref name, ref rules) => {
+                global.insert(name, Value::Syntax(rules.clone()));
+                Ok(())
+            }
+            CodeOp::Pop => {
                 self.stack.pop();
                 Ok(())
             }
+            CodeOp::PopHandler => {
+                if let Some(DumpOp::DumpHandler(_)) = self.dump.pop() {
+                    Ok(())
+                } else {
+                    Err(SchemeError::runtime("Runtime error: PopHandler"))
+                }
+            }
+            CodeOp::HandlerReturned => Err(SchemeError::runtime("handler returned from non-continuable raise")),
+        }
+    }
+
+    // tailが真のときは呼び出し元に戻る必要がないので、DumpAppを積まずに
+    // 現在のstackとenvを捨てる。プリミティブの場合は結果を積むだけで、
+    // 続くRtnがそのまま呼び出し元に値を返す。
+    fn app(&mut self, i: usize, tail: bool) -> Result<(), SchemeError> {
+        let procedure = self.stack.pop().ok_or("Runtime error: App")?;
+        let n = self.stack.len();
+        if i > n {
+            return Err(SchemeError::runtime("Runtime error: App"));
+        }
+        let args = self.stack.split_off(n - i);
+        self.apply_procedure(procedure, args, tail)
+    }
+
+    fn apply_procedure(&mut self, procedure: Value, args: Vec<Value>, tail: bool) -> Result<(), SchemeError> {
+        match procedure {
+            Value::Closure(lambda, env) => {
+                if !lambda.arity.accepts(args.len()) {
+                    return Err(SchemeError::arity_expected(&lambda.label(), lambda.arity, args.len()));
+                }
+                let env = Frame::extend(args, env);
+                let prev_stack = mem::take(&mut self.stack);
+                let prev_env = mem::replace(&mut self.env, env);
+                let clen = lambda.code.len();
+                let prev_code = mem::replace(&mut self.code, (lambda.code.clone(), clen - 1));
+                let prev_procedure = self.procedure.replace(lambda);
+
+                if !tail {
+                    self.dump
+                        .push(DumpOp::DumpApp(prev_stack, prev_env, prev_code, prev_procedure));
+                }
+                Ok(())
+            }
+            Value::Primitive(procedure) => {
+                let result = (procedure)(args)?;
+                self.stack.push(result);
+                Ok(())
+            }
+            Value::Native(native) => {
+                let result = native.call(args)?;
+                self.stack.push(result);
+                Ok(())
+            }
+            Value::Special(Special::CallCC) => {
+                if args.len() != 1 {
+                    return Err(SchemeError::arity("call/cc", args.len()));
+                }
+                let k = Rc::new(self.capture());
+                gc::register_continuation(&k);
+                let k = Value::Continuation(k);
+                let f = args.into_iter().next().unwrap_or(Value::Undefined);
+                self.apply_procedure(f, vec![k], tail)
+            }
+            Value::Special(Special::Raise) => {
+                if args.len() != 1 {
+                    return Err(SchemeError::arity("raise", args.len()));
+                }
+                let obj = args.into_iter().next().unwrap_or(Value::Undefined);
+                self.raise(obj, false, tail)
+            }
+            Value::Special(Special::RaiseContinuable) => {
+                if args.len() != 1 {
+                    return Err(SchemeError::arity("raise-continuable", args.len()));
+                }
+                let obj = args.into_iter().next().unwrap_or(Value::Undefined);
+                self.raise(obj, true, tail)
+            }
+            Value::Special(Special::WithExceptionHandler) => {
+                if args.len() != 2 {
+                    return Err(SchemeError::arity("with-exception-handler", args.len()));
+                }
+                let mut args = args.into_iter();
+                let handler = args.next().unwrap_or(Value::Undefined);
+                let thunk = args.next().unwrap_or(Value::Undefined);
+                let mut handlers = self.handlers();
+                handlers.push(handler);
+                // thunkから戻ったらハンドラを取り除き、with-exception-handlerの呼び出し元に戻る。
+                self.call_with_dump(DumpOp::DumpHandler(handlers),
+                                    vec![CodeOp::Rtn, CodeOp::PopHandler, CodeOp::App(0)],
+                                    vec![thunk],
+                                    tail);
+                Ok(())
+            }
+            Value::Special(Special::Error) => {
+                if args.is_empty() {
+                    return Err(SchemeError::arity("error", args.len()));
+                }
+                let message = args[0].to_owned();
+                let irritants = vec2cons(&args[1..], Value::Nil);
+                self.raise(Value::error(message, irritants), false, tail)
+            }
+            Value::Continuation(k) => {
+                let value = match args.len() {
+                    0 => Value::Undefined,
+                    1 => args.into_iter().next().unwrap_or(Value::Undefined),
+                    _ => return Err(SchemeError::arity("continuation", args.len())),
+                };
+                self.restore(&k);
+                self.stack.push(value);
+                Ok(())
+            }
+            ref v => Err(SchemeError::wrong_type("procedure", "apply", v)),
+        }
+    }
+
+    // ハンドラは外側のハンドラだけが有効な状態で呼ぶ。raise-continuableの場合は
+    // ハンドラの戻り値がそのままraise-continuableの戻り値になる。
+    fn raise(&mut self, obj: Value, continuable: bool, tail: bool) -> Result<(), SchemeError> {
+        let mut handlers = self.handlers();
+        let handler = match handlers.pop() {
+            Some(handler) => handler,
+            None => return Err(SchemeError::new(ErrorKind::Raise(obj))),
+        };
+        if continuable {
+            self.call_with_dump(DumpOp::DumpHandler(handlers),
+                                vec![CodeOp::Rtn, CodeOp::PopHandler, CodeOp::App(1)],
+                                vec![obj, handler],
+                                tail);
+        } else {
+            // 戻り先がないので呼び出し元の状態は保存しない。
+            self.call_with_dump(DumpOp::DumpHandler(handlers),
+                                vec![CodeOp::HandlerReturned, CodeOp::App(1)],
+                                vec![obj, handler],
+                                true);
+        }
+        Ok(())
+    }
+
+    // 現在の状態をDumpAppとして保存し(tailの場合は不要)、dumpにopを積んでから
+    // stackを初期値としてcodeを実行する。
+    fn call_with_dump(&mut self, op: DumpOp, code: Vec<CodeOp>, stack: Stack, tail: bool) {
+        let code: SharedCode = Rc::new(Code::from(code));
+        let clen = code.len();
+        let prev_stack = mem::replace(&mut self.stack, stack);
+        let prev_env = mem::take(&mut self.env);
+        let prev_code = mem::replace(&mut self.code, (code, clen - 1));
+        if !tail {
+            self.dump
+                .push(DumpOp::DumpApp(prev_stack, prev_env, prev_code, self.procedure.clone()));
+        }
+        self.dump.push(op);
+    }
+
+    fn handlers(&self) -> Vec<Value> {
+        for op in self.dump.iter().rev() {
+            if let DumpOp::DumpHandler(ref handlers) = *op {
+                return handlers.to_owned();
+            }
+        }
+        Vec::new()
+    }
+
+    // 継続の捕捉はcall/ccを呼んだ時点の制御状態のコピーを取るだけでよい。
+    // 末尾位置で呼ばれた場合もcodeは呼び出し元に戻るRtnを指しているので問題ない。
+    fn capture(&self) -> Continuation {
+        Frame::register(&self.env);
+        for op in &self.dump {
+            if let DumpOp::DumpApp(_, ref env, _, _) = *op {
+                Frame::register(env);
+            }
+        }
+        Continuation {
+            stack: self.stack.to_owned(),
+            env: self.env.to_owned(),
+            code: self.code.to_owned(),
+            dump: self.dump.to_owned(),
+            procedure: self.procedure.to_owned(),
+        }
+    }
+
+    fn restore(&mut self, k: &Continuation) {
+        self.stack = k.stack.to_owned();
+        self.env = k.env.to_owned();
+        self.code = k.code.to_owned();
+        self.dump = k.dump.to_owned();
+        self.procedure = k.procedure.to_owned();
+    }
+
+    // 実行中の手続きと、dumpに保存された呼び出し元の手続きの名前を内側から順に並べる。
+    // 再帰のように同じ手続きが続く部分は1つにまとめ、MAX_BACKTRACE個を超えた分は数だけ示す。
+    fn backtrace(&self) -> Vec<String> {
+        let callers = self.dump.iter().rev().filter_map(|op| match *op {
+            DumpOp::DumpApp(_, _, _, ref procedure) => Some(procedure),
+            _ => None,
+        });
+        let mut procedures = ::std::iter::once(&self.procedure).chain(callers).peekable();
+        let mut backtrace = Vec::new();
+        while backtrace.len() < MAX_BACKTRACE {
+            let procedure = match procedures.next() {
+                Some(procedure) => procedure,
+                None => return backtrace,
+            };
+            let mut repeats = 0;
+            while procedures.next_if(|next| same_procedure(next, procedure)).is_some() {
+                repeats += 1;
+            }
+            let label = match *procedure {
+                Some(ref lambda) => lambda.label(),
+                None => "<toplevel>".to_owned(),
+            };
+            if repeats == 0 {
+                backtrace.push(label);
+            } else {
+                backtrace.push(format!("{} ... {} more", label, repeats));
+            }
+        }
+        let rest = procedures.count();
+        if rest > 0 {
+            backtrace.push(format!("... {} more", rest));
+        }
+        backtrace
+    }
+
+    fn select<'a>(&mut self,
+                  conseq: &'a SharedCode,
+                  alt: &'a SharedCode)
+                  -> Result<&'a SharedCode, SchemeError> {
+        let value = self.stack.pop().ok_or("Runtime error: Sel")?;
+        if value == Value::Boolean(false) {
+            Ok(alt)
+        } else {
+            Ok(conseq)
         }
     }
 }
@@ -182,27 +766,111 @@ impl fmt::Debug for Machine {
     }
 }
 
-fn get_var(env: &[Vec<Value>], location: Location) -> Option<Value> {
-    let (i, j) = (location.0, location.1);
-    if let Some(frame) = env.get(i) {
-        match j {
-            Position::Index(index) => {
-                if index < frame.len() {
-                    Some(frame[index].to_owned())
-                } else {
-                    None
+impl Frame {
+    pub fn extend(values: Vec<Value>, parent: Env) -> Env {
+        Some(Rc::new(Frame {
+                         values: RefCell::new(values),
+                         parent,
+                         registered: Cell::new(false),
+                     }))
+    }
+
+    // フレームが循環の一部になるのは、クロージャや継続に捕捉されたときだけなので、
+    // 手続きの呼び出しごとではなく、捕捉するときに外側のフレームとあわせて登録する。
+    pub fn register(env: &Env) {
+        let mut env = env.as_ref();
+        while let Some(frame) = env {
+            if frame.registered.replace(true) {
+                break;
+            }
+            gc::register_frame(frame);
+            env = frame.parent.as_ref();
+        }
+    }
+
+    // GC用。フレームが持つ値の数。
+    pub fn size(&self) -> usize {
+        self.values.borrow().len()
+    }
+
+    // GC用。フレームの値と外側のフレームをそれぞれ渡す。借用中で調べられなければfalse。
+    pub fn trace(&self, visit: &mut dyn FnMut(Child)) -> bool {
+        match self.values.try_borrow() {
+            Ok(values) => {
+                for v in values.iter() {
+                    visit(Child::Value(v));
                 }
+                visit(Child::Env(&self.parent));
+                true
             }
-            Position::Rest(index) => {
-                if index <= frame.len() {
-                    let (_, rest) = frame.split_at(index);
-                    Some(vec2cons(rest, Value::Nil))
-                } else {
-                    None
+            Err(_) => false,
+        }
+    }
+
+    // GC用。到達できない循環を断ち切る。
+    pub fn clear(&self) {
+        self.values.borrow_mut().clear();
+    }
+
+    // 値と外側のフレームのうち、他から参照されていないものの解放を後回しにする。
+    pub fn defer_drop(&mut self, pending: &mut Vec<Garbage>) {
+        Value::defer_drop(self.values.get_mut(), pending);
+        defer_drop_env(&mut self.parent, pending);
+    }
+}
+
+// クロージャを通じて長く連なったフレームも、ペアと同じくループで解放する。
+impl Drop for Frame {
+    fn drop(&mut self) {
+        let mut pending = Vec::new();
+        self.defer_drop(&mut pending);
+        drop_pending(pending);
+    }
+}
+
+impl Continuation {
+    // GC用。捕捉したstack、env、dumpが持つ値と環境を渡す。
+    pub fn trace(&self, visit: &mut dyn FnMut(Child)) {
+        for v in &self.stack {
+            visit(Child::Value(v));
+        }
+        visit(Child::Env(&self.env));
+        for op in &self.dump {
+            match *op {
+                DumpOp::DumpApp(ref stack, ref env, _, _) => {
+                    for v in stack {
+                        visit(Child::Value(v));
+                    }
+                    visit(Child::Env(env));
+                }
+                DumpOp::DumpSel(_) => (),
+                DumpOp::DumpHandler(ref handlers) => {
+                    for v in handlers {
+                        visit(Child::Value(v));
+                    }
                 }
             }
         }
-    } else {
-        None
     }
 }
+
+fn lookup(env: &Env, depth: usize) -> Option<&Frame> {
+    let mut frame = env.as_ref()?;
+    for _ in 0..depth {
+        frame = frame.parent.as_ref()?;
+    }
+    Some(frame)
+}
+
+fn get_var(env: &Env, location: Location) -> Option<Value> {
+    let (i, j) = location;
+    lookup(env, i).and_then(|frame| frame.values.borrow().get(j).cloned())
+}
+
+fn set_var(env: &Env, location: Location, value: Value) -> Option<()> {
+    let (i, j) = location;
+    let mut values = lookup(env, i)?.values.borrow_mut();
+    let var = values.get_mut(j)?;
+    *var = value;
+    Some(())
+}
@@ -0,0 +1,46 @@
+extern crate secd;
+
+use secd::{Arity, Error, ErrorKind, Interpreter};
+
+fn eval(input: &str) -> String {
+    let mut interp = Interpreter::new().unwrap();
+    match interp.eval_str(input) {
+        Ok(value) => value.to_string(),
+        Err(e) => panic!("{}: {}", input, e),
+    }
+}
+
+fn eval_err(input: &str) -> String {
+    let mut interp = Interpreter::new().unwrap();
+    match interp.eval_str(input) {
+        Ok(value) => panic!("{}: evaluated to {}", input, value),
+        Err(e) => e.to_string(),
+    }
+}
+
+#[test]
+fn closures_reject_wrong_argument_counts() {
+    assert!(eval_err("(define (f a b) a) (f 1)").contains("expected 2 arguments, got 1 in f"));
+    assert!(eval_err("(define (f a b) a) (f 1 2 3)").contains("expected 2 arguments, got 3 in f"));
+    assert!(eval_err("(define (g a . rest) a) (g)").contains("expected at least 1 argument, got 0 in g"));
+}
+
+#[test]
+fn rest_arguments_collect_the_remainder() {
+    assert_eq!(eval("(define (g a . rest) (list a rest)) (list (g 1) (g 1 2 3))"), "((1 ()) (1 (2 3)))");
+    assert_eq!(eval("((lambda args args) 1 2)"), "(1 2)");
+}
+
+#[test]
+fn arity_errors_are_structured() {
+    let mut interp = Interpreter::new().unwrap();
+    match interp.eval_str("((lambda (x) x))") {
+        Err(Error::Scheme(e)) => {
+            match *e.kind {
+                ErrorKind::Arity { expected, got, .. } => assert_eq!((expected, got), (Some(Arity::Exactly(1)), 0)),
+                ref kind => panic!("unexpected error: {:?}", kind),
+            }
+        }
+        result => panic!("unexpected result: {:?}", result.map(|v| v.to_string())),
+    }
+}
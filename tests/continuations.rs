@@ -0,0 +1,52 @@
+extern crate secd;
+
+use secd::Interpreter;
+
+fn eval(input: &str) -> String {
+    let mut interp = Interpreter::new().unwrap();
+    match interp.eval_str(input) {
+        Ok(value) => value.to_string(),
+        Err(e) => panic!("{}: {}", input, e),
+    }
+}
+
+#[test]
+fn escaping_continuation() {
+    assert_eq!(eval("(+ 1 (call/cc (lambda (k) (+ 10 (k 2)))))"), "3");
+    assert_eq!(eval("
+(define (find-first pred l)
+  (call-with-current-continuation
+    (lambda (return)
+      (map (lambda (x) (if (pred x) (return x) #f)) l)
+      #f)))
+(list (find-first even? '(1 3 4 5)) (find-first even? '(1 3)))"),
+               "(4 #f)");
+}
+
+#[test]
+fn continuation_can_be_reentered() {
+    assert_eq!(eval("
+(let ((r '()) (k #f))
+  (let ((n (+ 100 (call/cc (lambda (c) (set! k c) 0)))))
+    (set! r (cons n r))
+    (if (< n 102) (k (- n 99)) r)))"),
+               "(102 101 100)");
+}
+
+#[test]
+fn reentered_continuation_restores_the_stack() {
+    assert_eq!(eval("
+(let ((saved #f) (count 0))
+  (let ((result (list 1 (call/cc (lambda (k) (set! saved k) 2)) 3)))
+    (set! count (+ count 1))
+    (if (< count 3) (saved (* count 10)) (list result count))))"),
+               "((1 20 3) 3)");
+}
+
+#[test]
+fn continuation_escapes_from_nested_calls() {
+    assert_eq!(eval("
+(define (deep n k) (if (= n 0) (k 'out) (+ 1 (deep (- n 1) k))))
+(call/cc (lambda (k) (deep 1000 k)))"),
+               "out");
+}
@@ -0,0 +1,70 @@
+extern crate secd;
+
+use std::collections::HashMap;
+use secd::{Error, ErrorKind, FromValue, Interpreter, IntoValue, Value};
+
+fn eval(interp: &mut Interpreter, input: &str) -> String {
+    match interp.eval_str(input) {
+        Ok(value) => value.to_string(),
+        Err(e) => panic!("{}: {}", input, e),
+    }
+}
+
+fn eval_err(interp: &mut Interpreter, input: &str) -> String {
+    match interp.eval_str(input) {
+        Ok(value) => panic!("{}: evaluated to {}", input, value),
+        Err(e) => e.to_string(),
+    }
+}
+
+#[test]
+fn rust_values_round_trip() {
+    assert_eq!(Vec::<i64>::from_value(&vec![1i64, 2, 3].into_value()), Ok(vec![1, 2, 3]));
+    assert_eq!(Option::<String>::from_value(&None::<String>.into_value()), Ok(None));
+    assert_eq!(<(i32, bool, char)>::from_value(&(7, true, 'x').into_value()), Ok((7, true, 'x')));
+    let mut map = HashMap::new();
+    map.insert("a".to_owned(), 1u8);
+    assert_eq!(HashMap::<String, u8>::from_value(&map.clone().into_value()), Ok(map));
+    assert_eq!(u64::MAX.into_value().to_string(), "18446744073709551615");
+}
+
+#[test]
+fn conversion_errors_name_expected_type() {
+    assert_eq!(i8::from_value(&Value::integer(300)), Err("8-bit integer".to_owned()));
+    assert_eq!(Vec::<i64>::from_value(&Value::integer(1)), Err("proper list".to_owned()));
+    assert_eq!(Option::<bool>::from_value(&Value::integer(1)), Err("boolean or #f".to_owned()));
+}
+
+#[test]
+fn typed_functions_check_arity_and_types() {
+    let mut interp = Interpreter::new().unwrap();
+    interp.register_fn("repeat", |n: i64, s: String| -> Result<Vec<String>, String> {
+        if n < 0 {
+            return Err("negative count: repeat".to_owned());
+        }
+        Ok(vec![s; n as usize])
+    });
+    assert_eq!(eval(&mut interp, "(repeat 2 \"ab\")"), "(\"ab\" \"ab\")");
+    assert!(eval_err(&mut interp, "(repeat 1)").contains("expected 2 arguments, got 1 in repeat"));
+    assert!(eval_err(&mut interp, "(repeat 'x \"ab\")").contains("integer required: repeat x"));
+    assert!(eval_err(&mut interp, "(repeat -1 \"ab\")").contains("negative count: repeat"));
+}
+
+#[test]
+fn builtin_primitives_use_typed_conversions() {
+    let mut interp = Interpreter::new().unwrap();
+    assert_eq!(eval(&mut interp, "(list->string (string->list \"abc\"))"), "\"abc\"");
+    assert_eq!(eval(&mut interp, "(vector-ref (list->vector '(1 2 3)) 2)"), "3");
+    assert!(eval_err(&mut interp, "(car 1 2)").contains("expected 1 argument, got 2 in car"));
+    match interp.eval_str("(vector-length '(1))") {
+        Err(Error::Scheme(e)) => {
+            match *e.kind {
+                ErrorKind::Type { ref expected, ref procedure, .. } => {
+                    assert_eq!((expected.as_str(), procedure.as_str()), ("vector", "vector-length"))
+                }
+                ref kind => panic!("unexpected error: {:?}", kind),
+            }
+        }
+        result => panic!("unexpected result: {:?}", result.map(|v| v.to_string())),
+    }
+}
@@ -0,0 +1,86 @@
+extern crate secd;
+
+use secd::Interpreter;
+
+fn eval(input: &str) -> String {
+    let mut interp = Interpreter::new().unwrap();
+    match interp.eval_str(input) {
+        Ok(value) => value.to_string(),
+        Err(e) => panic!("{}: {}", input, e),
+    }
+}
+
+fn eval_err(input: &str) -> String {
+    let mut interp = Interpreter::new().unwrap();
+    match interp.eval_str(input) {
+        Ok(value) => panic!("{}: evaluated to {}", input, value),
+        Err(e) => e.to_string(),
+    }
+}
+
+#[test]
+fn letrec_allows_mutual_recursion() {
+    assert_eq!(eval("
+(letrec ((even? (lambda (n) (if (= n 0) #t (odd? (- n 1)))))
+         (odd? (lambda (n) (if (= n 0) #f (even? (- n 1))))))
+  (list (even? 100) (odd? 7)))"),
+               "(#t #t)");
+}
+
+#[test]
+fn letrec_star_initializes_in_order() {
+    assert_eq!(eval("(letrec* ((x 1) (y (+ x 1)) (f (lambda () (* x y)))) (f))"), "2");
+}
+
+#[test]
+fn internal_definitions_are_mutually_recursive() {
+    assert_eq!(eval("
+(define (parity n)
+  (define (even? n) (if (= n 0) 'even (odd? (- n 1))))
+  (define (odd? n) (if (= n 0) 'odd (even? (- n 1))))
+  (even? n))
+(list (parity 10) (parity 7))"),
+               "(even odd)");
+}
+
+#[test]
+fn internal_definitions_shadow_globals() {
+    assert_eq!(eval("(define x 'global) (define (f) (define x 'local) x) (list (f) x)"),
+               "(local global)");
+}
+
+#[test]
+fn macros_can_produce_internal_definitions() {
+    assert_eq!(eval("
+(define-syntax define-pair
+  (syntax-rules ()
+    ((_ a b v) (begin (define a v) (define b v)))))
+(define-syntax define-one
+  (syntax-rules ()
+    ((_ name v) (define name v))))
+(define (f)
+  (define-one x 1)
+  (define-pair y z 2)
+  (+ x y z))
+(f)"),
+               "5");
+}
+
+#[test]
+fn begin_in_body_is_spliced() {
+    assert_eq!(eval("(define (f) (begin (define a 1) (define b 2)) (+ a b)) (f)"), "3");
+    assert_eq!(eval("(let () (begin (define a 1)) (define b (+ a 1)) (* a b))"), "2");
+}
+
+#[test]
+fn duplicate_names_are_rejected() {
+    assert!(eval_err("(letrec ((x 1) (x 2)) x)").contains("duplicate binding: x"));
+    assert!(eval_err("(letrec* ((x 1) (y 2) (x 3)) x)").contains("duplicate binding: x"));
+    assert!(eval_err("(define (f) (define x 1) (define x 2) x) (f)").contains("duplicate binding: x"));
+    assert!(eval_err("
+(define-syntax define-one
+  (syntax-rules ()
+    ((_ name v) (define name v))))
+(define (f) (define x 1) (define-one x 2) x)")
+        .contains("duplicate binding: x"));
+}
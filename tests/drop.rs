@@ -0,0 +1,50 @@
+extern crate secd;
+
+use secd::Interpreter;
+
+// 再帰して解放するとテストのスレッドのスタックを使い切る深さ。
+const DEPTH: usize = 100_000;
+
+fn build_and_drop(build: &str) {
+    let mut interp = Interpreter::new().unwrap();
+    interp.eval_str(build).unwrap();
+    interp.eval_str(&format!("(loop {})", DEPTH)).unwrap();
+    interp.eval_str("(set! x #f)").unwrap();
+}
+
+#[test]
+fn long_lists_are_dropped_iteratively() {
+    build_and_drop("(define x '()) (define (loop n) (if (= n 0) #t (begin (set! x (cons n x)) (loop (- n 1)))))");
+}
+
+#[test]
+fn nested_lists_are_dropped_iteratively() {
+    build_and_drop("(define x '()) (define (loop n) (if (= n 0) #t (begin (set! x (list x)) (loop (- n 1)))))");
+}
+
+#[test]
+fn nested_vectors_are_dropped_iteratively() {
+    build_and_drop("(define x '()) (define (loop n) (if (= n 0) #t (begin (set! x (vector x)) (loop (- n 1)))))");
+}
+
+#[test]
+fn closure_chains_are_dropped_iteratively() {
+    build_and_drop("
+(define x (lambda () #f))
+(define (loop n)
+  (if (= n 0) #t (begin (set! x (let ((g x)) (lambda () g))) (loop (- n 1)))))");
+}
+
+#[test]
+fn interpreter_is_dropped_iteratively() {
+    let mut interp = Interpreter::new().unwrap();
+    interp.eval_str("
+(define v '())
+(define f (lambda () #f))
+(define (loop n)
+  (if (= n 0)
+      #t
+      (begin (set! v (vector v)) (set! f (let ((g f)) (lambda () g))) (loop (- n 1)))))").unwrap();
+    interp.eval_str(&format!("(loop {})", DEPTH)).unwrap();
+    drop(interp);
+}
@@ -0,0 +1,52 @@
+extern crate secd;
+
+use std::env;
+use std::fs;
+use secd::{Error, Interpreter, Value};
+
+#[test]
+fn host_calls_scheme_procedures() {
+    let mut interp = Interpreter::new().unwrap();
+    interp.eval_str("(define (add3 a b c) (+ a b c))").unwrap();
+    let result = interp.call("add3", vec![Value::integer(1), Value::integer(2), Value::integer(3)]).unwrap();
+    assert_eq!(result.to_string(), "6");
+    assert!(interp.call("missing", Vec::new()).is_err());
+}
+
+#[test]
+fn host_defines_and_looks_up_globals() {
+    let mut interp = Interpreter::new().unwrap();
+    interp.define("greeting", Value::string("hello"));
+    assert_eq!(interp.eval_str("(string-append greeting \"!\")").unwrap().to_string(), "\"hello!\"");
+    interp.eval_str("(define answer 42)").unwrap();
+    assert_eq!(interp.lookup("answer").unwrap().to_string(), "42");
+    assert!(interp.names().iter().any(|name| name == "answer"));
+    assert!(interp.lookup("nothing").is_err());
+}
+
+#[test]
+fn interpreters_are_independent() {
+    let mut a = Interpreter::new().unwrap();
+    let mut b = Interpreter::new().unwrap();
+    a.eval_str("(define x 1)").unwrap();
+    assert!(b.eval_str("x").is_err());
+}
+
+#[test]
+fn eval_file_reports_io_and_source_errors() {
+    let mut interp = Interpreter::new().unwrap();
+    let dir = env::temp_dir();
+    let path = dir.join(format!("secd-embedding-{}.scm", std::process::id()));
+    fs::write(&path, "(define y 2)\n(* y 21)\n").unwrap();
+    let result = interp.eval_file(&path);
+    fs::remove_file(&path).unwrap();
+    assert_eq!(result.unwrap().to_string(), "42");
+    match interp.eval_file(dir.join("secd-embedding-missing.scm")) {
+        Err(Error::Io(_)) => (),
+        result => panic!("unexpected result: {:?}", result.map(|v| v.to_string())),
+    }
+    match interp.eval_str("(1 2") {
+        Err(Error::Read(_)) => (),
+        result => panic!("unexpected result: {:?}", result.map(|v| v.to_string())),
+    }
+}
@@ -0,0 +1,43 @@
+extern crate secd;
+
+use secd::Interpreter;
+
+fn eval(input: &str) -> String {
+    let mut interp = Interpreter::new().unwrap();
+    match interp.eval_str(input) {
+        Ok(value) => value.to_string(),
+        Err(e) => panic!("{}: {}", input, e),
+    }
+}
+
+#[test]
+fn eq_compares_identity() {
+    assert_eq!(eval("(list (eq? 'a 'a) (eq? '() '()) (eq? #t #t) (eq? car car))"), "(#t #t #t #t)");
+    assert_eq!(eval("(list (eq? (list 1) (list 1)) (eq? (string #\\a) (string #\\a)) (eq? (vector) (vector)))"),
+               "(#f #f #f)");
+    assert_eq!(eval("(define p (cons 1 2)) (eq? p p)"), "#t");
+    assert_eq!(eval("(define (f) (lambda () 1)) (eq? (f) (f))"), "#f");
+}
+
+#[test]
+fn eqv_compares_numbers_and_chars_by_value() {
+    assert_eq!(eval("(list (eqv? 2 2) (eqv? (expt 2 100) (expt 2 100)) (eqv? 1/2 (/ 2 4)) (eqv? #\\a #\\a))"),
+               "(#t #t #t #t)");
+    assert_eq!(eval("(list (eqv? 2 2.0) (eqv? 0.0 -0.0) (eqv? \"a\" \"a\") (eqv? (list) (list)))"),
+               "(#f #f #f #t)");
+}
+
+#[test]
+fn equal_compares_structure() {
+    assert_eq!(eval("(equal? (list 1 \"a\" #(2 (3)) #u8(4)) (list 1 \"a\" #(2 (3)) #u8(4)))"), "#t");
+    assert_eq!(eval("(list (equal? '(1 2) '(1 2 3)) (equal? 2 2.0) (equal? #(1) '(1)))"), "(#f #f #f)");
+}
+
+#[test]
+fn equal_terminates_on_circular_structure() {
+    assert_eq!(eval("
+(define a (list 1 2)) (set-cdr! (cdr a) a)
+(define b (list 1 2)) (set-cdr! (cdr b) b)
+(list (equal? a a) (equal? a b))"),
+               "(#t #t)");
+}
@@ -0,0 +1,41 @@
+extern crate secd;
+
+use secd::{Error, ErrorKind, Interpreter, SchemeError};
+
+fn scheme_error(input: &str) -> SchemeError {
+    let mut interp = Interpreter::new().unwrap();
+    match interp.eval_str(input) {
+        Err(Error::Scheme(e)) => e,
+        Err(e) => panic!("{}: unexpected error: {}", input, e),
+        Ok(value) => panic!("{}: evaluated to {}", input, value),
+    }
+}
+
+#[test]
+fn error_kinds_carry_offending_values() {
+    assert_eq!(*scheme_error("undefined-variable").kind, ErrorKind::UnboundVariable("undefined-variable".to_owned()));
+    let e = scheme_error("(car 5)");
+    assert_eq!(e.irritants().iter().map(ToString::to_string).collect::<Vec<_>>(), ["5"]);
+    let e = scheme_error("(error \"boom\" 1 'two \"three\")");
+    assert_eq!(e.message(), "boom");
+    assert_eq!(e.irritants().len(), 3);
+}
+
+#[test]
+fn backtrace_names_closures() {
+    let e = scheme_error("(define (f x) (car x)) (define (g x) (+ 1 (f x))) (g 5)");
+    let text = e.to_string();
+    let f = text.find("in f").expect(&text);
+    let g = text.find("in g").expect(&text);
+    assert!(f < g, "{}", text);
+}
+
+#[test]
+fn circular_irritants_are_finite() {
+    let e = scheme_error("
+(define e (guard (x (#t x)) (error \"boom\" 1 2 3)))
+(set-cdr! (cddr (error-object-irritants e)) (error-object-irritants e))
+(raise e)");
+    assert!(e.irritants().len() < 10);
+    assert!(e.to_string().starts_with("boom 1 2 3"));
+}
@@ -0,0 +1,67 @@
+extern crate secd;
+
+use secd::Interpreter;
+
+fn eval(input: &str) -> String {
+    let mut interp = Interpreter::new().unwrap();
+    match interp.eval_str(input) {
+        Ok(value) => value.to_string(),
+        Err(e) => panic!("{}: {}", input, e),
+    }
+}
+
+fn eval_err(input: &str) -> String {
+    let mut interp = Interpreter::new().unwrap();
+    match interp.eval_str(input) {
+        Ok(value) => panic!("{}: evaluated to {}", input, value),
+        Err(e) => e.to_string(),
+    }
+}
+
+#[test]
+fn guard_catches_raised_objects() {
+    assert_eq!(eval("(guard (e ((symbol? e) (list 'caught e))) (raise 'oops))"), "(caught oops)");
+    assert_eq!(eval("(guard (e ((string? e) => string-length) (else 'other)) (raise 42))"), "other");
+    assert_eq!(eval("(guard (e ((assq 'a e) => cdr) ((assq 'b e))) (raise (list (cons 'b 23))))"), "(b . 23)");
+}
+
+#[test]
+fn guard_reraises_when_no_clause_matches() {
+    assert_eq!(eval("(guard (outer (#t (list 'outer outer))) (guard (inner ((string? inner) 'inner)) (raise 'x)))"),
+               "(outer x)");
+    assert!(eval_err("(guard (e ((string? e) 'inner)) (raise 'x))").contains("x"));
+}
+
+#[test]
+fn guard_catches_vm_errors() {
+    assert_eq!(eval("(guard (e ((error-object? e) (error-object-message e))) (car 5))"),
+               "\"pair required: car\"");
+    assert_eq!(eval("(guard (e (#t (list (error-object-message e) (error-object-irritants e)))) (error \"boom\" 1 2))"),
+               "(\"boom\" (1 2))");
+}
+
+#[test]
+fn guard_body_allows_internal_definitions() {
+    assert_eq!(eval("(guard (e (#f 'no)) (define x 1) (define y (+ x 1)) (* x y 3))"), "6");
+}
+
+#[test]
+fn guard_is_hygienic() {
+    assert_eq!(eval("(define condition 'mine) (guard (e (#t (list e condition))) (raise 'x))"), "(x mine)");
+    assert_eq!(eval("(define let 5) (define cond 6) (guard (e (#t e)) (raise 'still-works))"), "still-works");
+}
+
+#[test]
+fn handler_result_is_returned_from_raise_continuable() {
+    assert_eq!(eval("(with-exception-handler (lambda (c) (* c 10)) (lambda () (+ 1 (raise-continuable 4))))"), "41");
+    assert_eq!(eval("
+(with-exception-handler
+  (lambda (c) 42)
+  (lambda () (+ 1 (guard (e ((string? e) 's)) (raise-continuable 'x)))))"),
+               "43");
+}
+
+#[test]
+fn returning_from_raise_handler_is_an_error() {
+    assert!(eval_err("(with-exception-handler (lambda (c) 0) (lambda () (raise 'x)))").contains("handler returned"));
+}
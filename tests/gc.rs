@@ -0,0 +1,67 @@
+extern crate secd;
+
+use secd::Interpreter;
+
+// 到達できない循環をいくつか作る。評価の途中で自動の回収が走らない程度の数にする。
+const MAKE_CYCLES: &str = "
+(define (make-cycle)
+  (let ((p (list 1 2 3)))
+    (set-cdr! (cdr (cdr p)) p)
+    #t))
+(define (loop n)
+  (if (= n 0) #t (begin (make-cycle) (loop (- n 1)))))
+(loop 100)";
+
+#[test]
+fn unreachable_cycles_are_reclaimed() {
+    let mut interp = Interpreter::new().unwrap();
+    interp.eval_str(MAKE_CYCLES).unwrap();
+    assert!(interp.collect_garbage() >= 300);
+    assert_eq!(interp.collect_garbage(), 0);
+}
+
+#[test]
+fn reachable_cycles_survive() {
+    let mut interp = Interpreter::new().unwrap();
+    interp.eval_str("(define p (list 1 2 3)) (set-cdr! (cdr (cdr p)) p)").unwrap();
+    interp.eval_str("(define v (vector 1 2)) (vector-set! v 0 v)").unwrap();
+    interp.collect_garbage();
+    assert_eq!(interp.eval_str("(car (cdr (cdr (cdr p))))").unwrap().to_string(), "1");
+    assert_eq!(interp.eval_str("(eq? v (vector-ref v 0))").unwrap().to_string(), "#t");
+}
+
+#[test]
+fn closures_in_cycles_are_reclaimed() {
+    let mut interp = Interpreter::new().unwrap();
+    interp.eval_str("
+(define (make-counter)
+  (letrec ((next (lambda () (next))))
+    next))
+(define (loop n)
+  (if (= n 0) #t (begin (make-counter) (loop (- n 1)))))
+(loop 100)").unwrap();
+    assert!(interp.collect_garbage() >= 100);
+}
+
+#[test]
+fn interpreters_collect_only_their_own_objects() {
+    let mut first = Interpreter::new().unwrap();
+    let mut second = Interpreter::new().unwrap();
+    first.eval_str(MAKE_CYCLES).unwrap();
+    assert_eq!(second.collect_garbage(), 0);
+    assert!(first.collect_garbage() >= 300);
+}
+
+#[test]
+fn continuations_in_cycles_are_reclaimed() {
+    let mut interp = Interpreter::new().unwrap();
+    interp.eval_str("
+(define (id x) x)
+(define (capture k)
+  (set! k (call/cc id))
+  #t)
+(define (loop n)
+  (if (= n 0) #t (begin (capture #f) (loop (- n 1)))))
+(loop 100)").unwrap();
+    assert!(interp.collect_garbage() >= 100);
+}
@@ -0,0 +1,38 @@
+extern crate secd;
+
+use secd::{Interpreter, Value};
+
+fn eval(interp: &mut Interpreter, input: &str) -> String {
+    match interp.eval_str(input) {
+        Ok(value) => value.to_string(),
+        Err(e) => panic!("{}: {}", input, e),
+    }
+}
+
+#[test]
+fn compiled_code_sees_redefinitions() {
+    let mut interp = Interpreter::new().unwrap();
+    eval(&mut interp, "(define (helper) 1) (define (use-helper) (helper))");
+    assert_eq!(eval(&mut interp, "(use-helper)"), "1");
+    eval(&mut interp, "(define (helper) 2)");
+    assert_eq!(eval(&mut interp, "(use-helper)"), "2");
+    interp.define("helper", Value::integer(3));
+    assert_eq!(eval(&mut interp, "(define (use-value) helper) (use-value)"), "3");
+}
+
+#[test]
+fn forward_references_resolve_when_defined() {
+    let mut interp = Interpreter::new().unwrap();
+    eval(&mut interp, "(define (early) (later))");
+    assert!(interp.eval_str("(early)").unwrap_err().to_string().contains("unbound variable: later"));
+    eval(&mut interp, "(define (later) 'ok)");
+    assert_eq!(eval(&mut interp, "(early)"), "ok");
+}
+
+#[test]
+fn builtins_can_be_redefined() {
+    let mut interp = Interpreter::new().unwrap();
+    eval(&mut interp, "(define (first l) (car l))");
+    eval(&mut interp, "(define car cdr)");
+    assert_eq!(eval(&mut interp, "(first '(1 2))"), "(2)");
+}
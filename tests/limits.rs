@@ -0,0 +1,132 @@
+extern crate secd;
+
+use std::thread;
+use std::time::Duration;
+use secd::{Error, ErrorKind, Execution, Interpreter, Limit, Limits, Value};
+
+fn limited(limits: Limits) -> Interpreter {
+    let mut interp = Interpreter::new().unwrap();
+    interp.set_limits(limits);
+    interp
+}
+
+fn exceeded(result: Result<Value, Error>) -> Option<Limit> {
+    match result {
+        Err(Error::Scheme(e)) => {
+            match *e.kind {
+                ErrorKind::LimitExceeded(limit) => Some(limit),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+#[test]
+fn steps_limit_stops_infinite_loop() {
+    let mut interp = limited(Limits { steps: Some(10_000), ..Limits::default() });
+    let result = interp.eval_str("(define (loop) (loop)) (loop)");
+    assert_eq!(exceeded(result), Some(Limit::Steps));
+    // 上限は評価ごとに数え直す。
+    assert_eq!(interp.eval_str("(+ 1 2)").unwrap().to_string(), "3");
+}
+
+#[test]
+fn steps_limit_counts_macro_expansion() {
+    let mut interp = limited(Limits { steps: Some(100), ..Limits::default() });
+    let result = interp.eval_str("(define-syntax forever (syntax-rules () ((_) (forever)))) (forever)");
+    assert_eq!(exceeded(result), Some(Limit::Steps));
+}
+
+#[test]
+fn dump_depth_limit_stops_deep_recursion() {
+    let mut interp = limited(Limits { dump_depth: Some(100), ..Limits::default() });
+    let result = interp.eval_str("(define (f n) (+ 1 (f n))) (f 0)");
+    assert_eq!(exceeded(result), Some(Limit::DumpDepth));
+}
+
+#[test]
+fn time_limit_stops_infinite_loop() {
+    let mut interp = limited(Limits { time: Some(Duration::from_millis(50)), ..Limits::default() });
+    let result = interp.eval_str("(define (loop) (loop)) (loop)");
+    assert_eq!(exceeded(result), Some(Limit::Time));
+}
+
+#[test]
+fn time_limit_counts_macro_expansion() {
+    // 展開のたびに式が倍になるので、数十回の展開で止まらなければ終わらない。
+    let mut interp = limited(Limits { time: Some(Duration::from_millis(200)), ..Limits::default() });
+    let result = interp.eval_str("
+(define-syntax grow (syntax-rules () ((_ x ...) (grow x ... x ...))))
+(grow 1)");
+    assert_eq!(exceeded(result), Some(Limit::Time));
+}
+
+#[test]
+fn heap_limit_counts_square() {
+    let mut interp = limited(Limits { heap: Some(10_000), ..Limits::default() });
+    let result = interp.eval_str("(define (grow n) (grow (square n))) (grow 3)");
+    assert_eq!(exceeded(result), Some(Limit::Heap));
+}
+
+#[test]
+fn time_limit_excludes_time_suspended() {
+    let mut interp = limited(Limits { time: Some(Duration::from_millis(200)), ..Limits::default() });
+    let mut execution = interp.start("<test>",
+                                     "(define (loop n) (if (= n 0) 'done (loop (- n 1)))) (loop 10000)",
+                                     10_000)
+        .unwrap();
+    loop {
+        match execution {
+            Execution::Done(value) => {
+                assert_eq!(value.to_string(), "done");
+                break;
+            }
+            Execution::Suspended(suspended) => {
+                thread::sleep(Duration::from_millis(30));
+                execution = interp.resume(suspended, Value::Undefined, 10_000).unwrap();
+            }
+        }
+    }
+}
+
+#[test]
+fn heap_limit_is_checked_before_allocating() {
+    let mut interp = limited(Limits { heap: Some(10_000), ..Limits::default() });
+    assert_eq!(exceeded(interp.eval_str("(make-bytevector 1000000000)")), Some(Limit::Heap));
+    assert_eq!(exceeded(interp.eval_str("(make-vector 1000000000)")), Some(Limit::Heap));
+    assert_eq!(exceeded(interp.eval_str("(make-string 1000000000 #\\a)")), Some(Limit::Heap));
+    assert_eq!(interp.eval_str("(vector-length (make-vector 100))").unwrap().to_string(), "100");
+}
+
+#[test]
+fn heap_limit_counts_strings_and_bignums() {
+    let mut interp = limited(Limits { heap: Some(10_000), ..Limits::default() });
+    let result = interp.eval_str("
+(define (grow s) (grow (string-append s s)))
+(grow \"abcdefgh\")");
+    assert_eq!(exceeded(result), Some(Limit::Heap));
+    let result = interp.eval_str("(define (square n) (square (* n n))) (square 3)");
+    assert_eq!(exceeded(result), Some(Limit::Heap));
+}
+
+#[test]
+fn heap_limit_counts_live_lists() {
+    let mut interp = limited(Limits { heap: Some(10_000), ..Limits::default() });
+    let result = interp.eval_str("(define (build n acc) (build (+ n 1) (cons n acc))) (build 0 '())");
+    assert_eq!(exceeded(result), Some(Limit::Heap));
+}
+
+#[test]
+fn heap_limit_allows_garbage() {
+    let mut interp = limited(Limits { heap: Some(10_000), steps: Some(10_000_000), ..Limits::default() });
+    let result = interp.eval_str("
+(define (make-cycle)
+  (let ((p (list 1 2 3)))
+    (set-cdr! (cdr (cdr p)) p)
+    (make-string 100)))
+(define (loop n)
+  (if (= n 0) 'done (begin (make-cycle) (loop (- n 1)))))
+(loop 20000)");
+    assert_eq!(result.unwrap().to_string(), "done");
+}
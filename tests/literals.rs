@@ -0,0 +1,39 @@
+extern crate secd;
+
+use secd::Interpreter;
+
+fn eval(input: &str) -> String {
+    let mut interp = Interpreter::new().unwrap();
+    match interp.eval_str(input) {
+        Ok(value) => value.to_string(),
+        Err(e) => panic!("{}: {}", input, e),
+    }
+}
+
+fn eval_err(input: &str) -> String {
+    let mut interp = Interpreter::new().unwrap();
+    match interp.eval_str(input) {
+        Ok(value) => panic!("{}: evaluated to {}", input, value),
+        Err(e) => e.to_string(),
+    }
+}
+
+#[test]
+fn literals_are_built_once() {
+    assert_eq!(eval("(define (f) '(1 (2) #(3))) (eq? (f) (f))"), "#t");
+    assert_eq!(eval("(define (s) \"abc\") (eq? (s) (s))"), "#t");
+    assert_eq!(eval("(define (v) #(1 2)) (eq? (v) (v))"), "#t");
+}
+
+#[test]
+fn quasiquote_builds_fresh_lists() {
+    assert_eq!(eval("(define (f x) `(a ,x)) (eq? (f 1) (f 1))"), "#f");
+    assert_eq!(eval("(define l `(a ,(+ 1 1))) (set-car! l 'b) l"), "(b 2)");
+}
+
+#[test]
+fn nested_literals_are_immutable() {
+    assert!(eval_err("(define (f) '(1 (2))) (set-car! (cadr (f)) 'x)").contains("mutable pair required"));
+    assert!(eval_err("(vector-set! (vector-ref '#(#(1)) 0) 0 'x)").contains("vector-set!"));
+    assert_eq!(eval("(define (f) '(1 2)) (guard (e (#t (f))) (set-car! (f) 'x))"), "(1 2)");
+}
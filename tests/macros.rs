@@ -0,0 +1,65 @@
+extern crate secd;
+
+use secd::Interpreter;
+
+fn eval(input: &str) -> String {
+    let mut interp = Interpreter::new().unwrap();
+    match interp.eval_str(input) {
+        Ok(value) => value.to_string(),
+        Err(e) => panic!("{}: {}", input, e),
+    }
+}
+
+fn eval_err(input: &str) -> String {
+    let mut interp = Interpreter::new().unwrap();
+    match interp.eval_str(input) {
+        Ok(value) => panic!("{}: evaluated to {}", input, value),
+        Err(e) => e.to_string(),
+    }
+}
+
+#[test]
+fn introduced_bindings_do_not_capture_user_variables() {
+    assert_eq!(eval("
+(define-syntax swap!
+  (syntax-rules () ((_ a b) (let ((tmp a)) (set! a b) (set! b tmp)))))
+(define tmp 1) (define other 2)
+(swap! tmp other)
+(list tmp other)"),
+               "(2 1)");
+}
+
+#[test]
+fn free_identifiers_refer_to_definition_site() {
+    assert_eq!(eval("
+(define-syntax my-or2
+  (syntax-rules () ((_ a b) (let ((t a)) (if t t b)))))
+(let ((if list) (t 5)) (my-or2 #f t))"),
+               "5");
+}
+
+#[test]
+fn literals_and_nested_ellipses() {
+    assert_eq!(eval("
+(define-syntax arrows
+  (syntax-rules (=>) ((_ (a => b ...) ...) '((b ... a) ...))))
+(arrows (1 => 2 3) (4 => 5))"),
+               "((2 3 1) (5 4))");
+    assert!(eval_err("
+(define-syntax only-arrow (syntax-rules (=>) ((_ =>) 'ok)))
+(only-arrow x)").contains("only-arrow"));
+}
+
+#[test]
+fn recursive_macros() {
+    assert_eq!(eval("
+(define-syntax my-and
+  (syntax-rules () ((_) #t) ((_ e) e) ((_ e r ...) (if e (my-and r ...) #f))))
+(list (my-and) (my-and 1 2 3) (my-and 1 #f 3))"),
+               "(#t 3 #f)");
+}
+
+#[test]
+fn define_macro_still_works() {
+    assert_eq!(eval("(define-macro (unless c . body) `(if ,c #f (begin ,@body))) (unless #f 1 2)"), "2");
+}
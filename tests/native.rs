@@ -0,0 +1,62 @@
+extern crate secd;
+
+use std::cell::Cell;
+use std::rc::Rc;
+use secd::{Arity, FromValue, Interpreter, Value};
+
+fn eval(interp: &mut Interpreter, input: &str) -> String {
+    match interp.eval_str(input) {
+        Ok(value) => value.to_string(),
+        Err(e) => panic!("{}: {}", input, e),
+    }
+}
+
+fn eval_err(interp: &mut Interpreter, input: &str) -> String {
+    match interp.eval_str(input) {
+        Ok(value) => panic!("{}: evaluated to {}", input, value),
+        Err(e) => e.to_string(),
+    }
+}
+
+#[test]
+fn natives_keep_state() {
+    let mut interp = Interpreter::new().unwrap();
+    let count = Rc::new(Cell::new(0));
+    let counter = count.clone();
+    interp.register("tick!", Arity::Exactly(0), move |_| {
+        counter.set(counter.get() + 1);
+        Ok(Value::integer(counter.get()))
+    });
+    assert_eq!(eval(&mut interp, "(tick!) (tick!) (tick!)"), "3");
+    assert_eq!(count.get(), 3);
+}
+
+#[test]
+fn natives_are_first_class() {
+    let mut interp = Interpreter::new().unwrap();
+    interp.register("twice", Arity::Exactly(1), |args| {
+        Ok(Value::integer(i64::from_value(&args[0])? * 2))
+    });
+    assert_eq!(eval(&mut interp, "(map twice '(1 2 3))"), "(2 4 6)");
+    assert_eq!(eval(&mut interp, "twice"), "#<subr twice>");
+}
+
+#[test]
+fn native_arity_is_checked() {
+    let mut interp = Interpreter::new().unwrap();
+    interp.register("pair-up", Arity::Between(1, 2), |args| {
+        Ok(Value::cons(args[0].clone(), args.get(1).cloned().unwrap_or(Value::Nil)))
+    });
+    interp.register("count", Arity::AtLeast(1), |args| Ok(Value::integer(args.len() as i64)));
+    assert_eq!(eval(&mut interp, "(list (pair-up 1) (pair-up 1 2) (count 'a 'b 'c))"), "((1) (1 . 2) 3)");
+    assert!(eval_err(&mut interp, "(pair-up)").contains("expected 1 to 2 arguments, got 0 in pair-up"));
+    assert!(eval_err(&mut interp, "(count)").contains("expected at least 1 argument, got 0 in count"));
+}
+
+#[test]
+fn native_errors_become_scheme_errors() {
+    let mut interp = Interpreter::new().unwrap();
+    interp.register("fail", Arity::Exactly(0), |_| Err("host failure".to_owned()));
+    assert_eq!(eval(&mut interp, "(guard (e ((error-object? e) (error-object-message e))) (fail))"),
+               "\"host failure\"");
+}
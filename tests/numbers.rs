@@ -0,0 +1,63 @@
+extern crate secd;
+
+use secd::{Error, ErrorKind, Interpreter, Limit, Limits};
+
+fn eval(interp: &mut Interpreter, input: &str) -> String {
+    match interp.eval_str(input) {
+        Ok(value) => value.to_string(),
+        Err(e) => panic!("{}: {}", input, e),
+    }
+}
+
+#[test]
+fn exact_decimal_exponents_are_bounded() {
+    let mut interp = Interpreter::new().unwrap();
+    assert_eq!(eval(&mut interp, "(= #e1e400 (expt 10 400))"), "#t");
+    assert_eq!(eval(&mut interp, "#e1.5e-3"), "3/2000");
+    assert_eq!(eval(&mut interp, "(string->number \"#e1e16000000\")"), "#f");
+    assert_eq!(eval(&mut interp, "(string->number \"#e1e-16000000\")"), "#f");
+    assert!(interp.eval_str("#e1e3000000").is_err());
+}
+
+#[test]
+fn string_to_number_is_charged_to_the_heap() {
+    let mut interp = Interpreter::new().unwrap();
+    interp.set_limits(Limits { heap: Some(200), ..Limits::default() });
+    match interp.eval_str("(string->number \"#e1e9000\")") {
+        Err(Error::Scheme(e)) => assert_eq!(*e.kind, ErrorKind::LimitExceeded(Limit::Heap)),
+        result => panic!("unexpected result: {:?}", result.map(|v| v.to_string().len())),
+    }
+}
+
+#[test]
+fn integers_promote_to_bignums() {
+    let mut interp = Interpreter::new().unwrap();
+    assert_eq!(eval(&mut interp, "(* 9223372036854775807 2)"), "18446744073709551614");
+    assert_eq!(eval(&mut interp, "(- (+ 9223372036854775807 1) 1)"), "9223372036854775807");
+    assert_eq!(eval(&mut interp, "(expt 2 100)"), "1267650600228229401496703205376");
+}
+
+#[test]
+fn exact_division_gives_rationals() {
+    let mut interp = Interpreter::new().unwrap();
+    assert_eq!(eval(&mut interp, "(/ 1 3)"), "1/3");
+    assert_eq!(eval(&mut interp, "(+ 1/3 2/3)"), "1");
+    assert_eq!(eval(&mut interp, "(list (numerator 6/4) (denominator 6/4))"), "(3 2)");
+    assert!(interp.eval_str("(/ 1 0)").is_err());
+}
+
+#[test]
+fn inexact_is_contagious() {
+    let mut interp = Interpreter::new().unwrap();
+    assert_eq!(eval(&mut interp, "(+ 1/2 0.5)"), "1.0");
+    assert_eq!(eval(&mut interp, "(list (exact? (* 2 1.0)) (exact? (* 2 1/2)))"), "(#f #t)");
+    assert_eq!(eval(&mut interp, "(exact 2.5)"), "5/2");
+    assert_eq!(eval(&mut interp, "(= 1 1.0 2/2)"), "#t");
+}
+
+#[test]
+fn reader_prefixes() {
+    let mut interp = Interpreter::new().unwrap();
+    assert_eq!(eval(&mut interp, "(list #xff #b101 #o17 #i1/2 #e0.25)"), "(255 5 15 0.5 1/4)");
+    assert_eq!(eval(&mut interp, "(list (string->number \"#x10\") (string->number \"1/0\"))"), "(16 #f)");
+}
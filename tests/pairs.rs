@@ -0,0 +1,51 @@
+extern crate secd;
+
+use secd::Interpreter;
+
+fn eval(input: &str) -> String {
+    let mut interp = Interpreter::new().unwrap();
+    match interp.eval_str(input) {
+        Ok(value) => value.to_string(),
+        Err(e) => panic!("{}: {}", input, e),
+    }
+}
+
+fn eval_err(input: &str) -> String {
+    let mut interp = Interpreter::new().unwrap();
+    match interp.eval_str(input) {
+        Ok(value) => panic!("{}: evaluated to {}", input, value),
+        Err(e) => e.to_string(),
+    }
+}
+
+#[test]
+fn set_car_and_set_cdr_mutate_shared_pairs() {
+    assert_eq!(eval("(define p (list 1 2 3)) (define q p) (set-car! p 'a) (set-cdr! (cdr p) '()) q"),
+               "(a 2)");
+}
+
+#[test]
+fn list_set_replaces_element() {
+    assert_eq!(eval("(define l (list 1 2 3)) (list-set! l 1 'x) l"), "(1 x 3)");
+    assert!(eval_err("(list-set! (list 1 2) 2 'x)").contains("index out of range"));
+}
+
+#[test]
+fn list_set_rejects_circular_list() {
+    assert!(eval_err("(define c (list 1 2 3)) (set-cdr! (cddr c) c) (list-set! c 100000000000 'x)")
+        .contains("proper list"));
+}
+
+#[test]
+fn append_and_reverse_in_place() {
+    assert_eq!(eval("(define a (list 1 2)) (define b (list 3)) (append! a '() b) a"), "(1 2 3)");
+    assert_eq!(eval("(define l (list 1 2 3)) (define r (reverse! l)) (list r l)"), "((3 2 1) (1))");
+}
+
+#[test]
+fn literals_are_immutable() {
+    assert!(eval_err("(define (f) '(1 2)) (set-car! (f) 'x)").contains("mutable pair required"));
+    assert!(eval_err("(reverse! '(1 2 3))").contains("mutable pair required"));
+    // 失敗したreverse!はリストを壊さない。
+    assert_eq!(eval("(define l (cons 1 '(2 3))) (guard (e (#t l)) (reverse! l))"), "(1 2 3)");
+}
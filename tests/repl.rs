@@ -0,0 +1,30 @@
+extern crate secd;
+
+use secd::is_incomplete;
+
+#[test]
+fn unclosed_input_waits_for_more_lines() {
+    assert!(is_incomplete("(define (f x)"));
+    assert!(is_incomplete("(define (f x)\n  (+ x"));
+    assert!(is_incomplete("#(1 2"));
+    assert!(is_incomplete("\"abc"));
+    assert!(is_incomplete("'"));
+    assert!(is_incomplete("(list ,@"));
+}
+
+#[test]
+fn complete_input_is_evaluated() {
+    assert!(!is_incomplete(""));
+    assert!(!is_incomplete("(define (f x) x)"));
+    assert!(!is_incomplete("'a"));
+    assert!(!is_incomplete("(+ 1 2))"));
+}
+
+#[test]
+fn parens_in_strings_chars_and_comments_are_ignored() {
+    assert!(!is_incomplete("\"(\""));
+    assert!(!is_incomplete("\"a\\\"(\""));
+    assert!(!is_incomplete("(list #\\( #\\))"));
+    assert!(!is_incomplete("1 ; (unclosed"));
+    assert!(is_incomplete("(a ; )\n"));
+}
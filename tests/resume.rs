@@ -0,0 +1,113 @@
+extern crate secd;
+
+use secd::{Arity, Error, Execution, Interpreter, SchemeError, Suspended, Value};
+
+const COUNT: &str = "(define (count n acc) (if (= n 0) acc (count (- n 1) (+ acc 1)))) (count 10000 0)";
+
+fn suspended(execution: Execution) -> Suspended {
+    match execution {
+        Execution::Suspended(suspended) => suspended,
+        Execution::Done(value) => panic!("finished with {}", value),
+    }
+}
+
+// 中断した評価を、終わるまでstepsずつ進める。中断した回数も返す。
+fn finish(interp: &mut Interpreter, mut execution: Execution, steps: u64) -> (Value, usize) {
+    let mut pauses = 0;
+    loop {
+        match execution {
+            Execution::Done(value) => return (value, pauses),
+            Execution::Suspended(suspended) => {
+                pauses += 1;
+                execution = interp.resume(suspended, Value::Undefined, steps).unwrap();
+            }
+        }
+    }
+}
+
+#[test]
+fn pauses_after_steps_and_resumes() {
+    let mut interp = Interpreter::new().unwrap();
+    let execution = interp.start("<test>", COUNT, 1000).unwrap();
+    let (value, pauses) = finish(&mut interp, execution, 1000);
+    assert_eq!(value.to_string(), "10000");
+    assert!(pauses > 10);
+}
+
+#[test]
+fn finishes_without_pausing_when_steps_suffice() {
+    let mut interp = Interpreter::new().unwrap();
+    match interp.start("<test>", "(define x 1) (+ x 2)", 1_000_000).unwrap() {
+        Execution::Done(value) => assert_eq!(value.to_string(), "3"),
+        Execution::Suspended(_) => panic!("suspended"),
+    }
+}
+
+#[test]
+fn later_expressions_see_earlier_definitions() {
+    let mut interp = Interpreter::new().unwrap();
+    let execution = interp.start("<test>", "(define (f) 'first) (define g (f)) (define (f) 'second) (list g (f))", 1)
+        .unwrap();
+    let (value, _) = finish(&mut interp, execution, 1);
+    assert_eq!(value.to_string(), "(first second)");
+}
+
+#[test]
+fn primitive_yields_to_host() {
+    let mut interp = Interpreter::new().unwrap();
+    interp.define("ask",
+                  Value::native("ask", Arity::Exactly(1), |args| {
+                      Err(SchemeError::yield_to_host(args[0].clone()))
+                  }));
+    let execution = interp.start("<test>", "(+ (ask 'x) (ask 'y))", 1000).unwrap();
+    let first = suspended(execution);
+    assert_eq!(first.yielded().map(Value::to_string), Some("x".to_owned()));
+    let second = suspended(interp.resume(first, Value::integer(1), 1000).unwrap());
+    assert_eq!(second.yielded().map(Value::to_string), Some("y".to_owned()));
+    match interp.resume(second, Value::integer(2), 1000).unwrap() {
+        Execution::Done(value) => assert_eq!(value.to_string(), "3"),
+        Execution::Suspended(_) => panic!("suspended"),
+    }
+}
+
+#[test]
+fn yield_is_an_error_outside_start() {
+    let mut interp = Interpreter::new().unwrap();
+    interp.define("ask",
+                  Value::native("ask", Arity::Exactly(0), |_| Err(SchemeError::yield_to_host(Value::Undefined))));
+    assert!(interp.eval_str("(ask)").is_err());
+}
+
+#[test]
+fn evaluations_are_interleaved() {
+    let mut interp = Interpreter::new().unwrap();
+    let mut first = suspended(interp.start("<first>", COUNT, 100).unwrap());
+    let mut second = suspended(interp.start("<second>", "(define (count2 n) (if (= n 0) 'two (count2 (- n 1)))) (count2 5000)", 100)
+        .unwrap());
+    let results = loop {
+        match (interp.resume(first, Value::Undefined, 100).unwrap(), interp.resume(second, Value::Undefined, 100).unwrap()) {
+            (Execution::Suspended(a), Execution::Suspended(b)) => {
+                first = a;
+                second = b;
+            }
+            (a, b) => {
+                let a = finish(&mut interp, a, 100).0;
+                let b = finish(&mut interp, b, 100).0;
+                break (a.to_string(), b.to_string());
+            }
+        }
+    };
+    assert_eq!(results, ("10000".to_owned(), "two".to_owned()));
+}
+
+#[test]
+fn cannot_resume_in_another_interpreter() {
+    let mut first = Interpreter::new().unwrap();
+    let mut second = Interpreter::new().unwrap();
+    let execution = suspended(first.start("<test>", COUNT, 100).unwrap());
+    match second.resume(execution, Value::Undefined, 100) {
+        Err(Error::Scheme(e)) => assert!(e.to_string().contains("another interpreter")),
+        Err(e) => panic!("unexpected error: {}", e),
+        Ok(_) => panic!("resumed in another interpreter"),
+    }
+}
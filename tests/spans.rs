@@ -0,0 +1,42 @@
+extern crate secd;
+
+use secd::{Error, Interpreter, SchemeError};
+
+fn scheme_error(name: &str, input: &str) -> SchemeError {
+    let mut interp = Interpreter::new().unwrap();
+    match interp.eval_source(name, input) {
+        Err(Error::Scheme(e)) => e,
+        Err(e) => panic!("{}: unexpected error: {}", input, e),
+        Ok(value) => panic!("{}: evaluated to {}", input, value),
+    }
+}
+
+fn position(e: &SchemeError) -> (String, usize, usize) {
+    let span = e.span.as_ref().expect("no span");
+    (span.source.name.clone(), span.line, span.column)
+}
+
+#[test]
+fn runtime_errors_point_at_the_failing_call() {
+    let e = scheme_error("main.scm", "(define x 1)\n(+ x\n   (car x))");
+    assert_eq!(position(&e), ("main.scm".to_owned(), 3, 4));
+    assert!(e.to_string().contains("at main.scm:3:4\n       (car x))\n       ^"), "{}", e);
+}
+
+#[test]
+fn errors_in_procedure_bodies_point_into_the_body() {
+    let e = scheme_error("lib.scm", "(define (f x)\n  (vector-ref x 0))\n(f 1)");
+    assert_eq!(position(&e), ("lib.scm".to_owned(), 2, 3));
+}
+
+#[test]
+fn syntax_errors_have_positions() {
+    let e = scheme_error("bad.scm", "\n  (lambda)");
+    assert_eq!(position(&e), ("bad.scm".to_owned(), 2, 3));
+}
+
+#[test]
+fn unbound_variables_point_at_the_innermost_form() {
+    let e = scheme_error("vars.scm", "(list 1 (car nowhere))");
+    assert_eq!(position(&e), ("vars.scm".to_owned(), 1, 9));
+}
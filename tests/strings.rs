@@ -0,0 +1,47 @@
+extern crate secd;
+
+use secd::Interpreter;
+
+fn eval(input: &str) -> String {
+    let mut interp = Interpreter::new().unwrap();
+    match interp.eval_str(input) {
+        Ok(value) => value.to_string(),
+        Err(e) => panic!("{}: {}", input, e),
+    }
+}
+
+fn eval_err(input: &str) -> String {
+    let mut interp = Interpreter::new().unwrap();
+    match interp.eval_str(input) {
+        Ok(value) => panic!("{}: evaluated to {}", input, value),
+        Err(e) => e.to_string(),
+    }
+}
+
+#[test]
+fn string_literals_and_escapes() {
+    assert_eq!(eval(r#""a\"b\\c\n""#), r#""a\"b\\c\n""#);
+    assert_eq!(eval(r#"(string-length "héllo")"#), "5");
+    assert_eq!(eval(r#"(string-ref "héllo" 1)"#), r"#\é");
+}
+
+#[test]
+fn character_literals() {
+    assert_eq!(eval(r"(list #\a #\space #\newline #\x41)"), r"(#\a #\space #\newline #\A)");
+    assert_eq!(eval(r"(list (char->integer #\A) (integer->char 955))"), r"(65 #\λ)");
+    assert_eq!(eval(r"(list (char-upcase #\a) (char-numeric? #\7) (char<? #\a #\b #\c))"), r"(#\A #t #t)");
+}
+
+#[test]
+fn string_procedures() {
+    assert_eq!(eval(r#"(string-append "ab" "" "cd")"#), r#""abcd""#);
+    assert_eq!(eval(r#"(substring "hello" 1 3)"#), r#""el""#);
+    assert_eq!(eval(r#"(list (string=? "a" "a") (string<? "a" "b") (string-upcase "abc"))"#), r#"(#t #t "ABC")"#);
+    assert_eq!(eval(r#"(list (symbol->string 'foo) (string->symbol "bar"))"#), r#"("foo" bar)"#);
+}
+
+#[test]
+fn string_index_is_checked() {
+    assert!(eval_err(r#"(string-ref "abc" 3)"#).contains("string-ref"));
+    assert!(eval_err(r#"(substring "abc" 2 1)"#).contains("substring"));
+}
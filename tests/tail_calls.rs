@@ -0,0 +1,45 @@
+extern crate secd;
+
+use secd::{Interpreter, Limits};
+
+// dumpが深くなればエラーになるので、末尾呼び出しでdumpが伸びないことを確かめられる。
+fn eval_shallow(input: &str) -> String {
+    let mut interp = Interpreter::new().unwrap();
+    interp.set_limits(Limits { dump_depth: Some(64), ..Limits::default() });
+    match interp.eval_str(input) {
+        Ok(value) => value.to_string(),
+        Err(e) => panic!("{}: {}", input, e),
+    }
+}
+
+#[test]
+fn self_tail_call_runs_in_constant_dump() {
+    assert_eq!(eval_shallow("(define (count n acc) (if (= n 0) acc (count (- n 1) (+ acc 1)))) (count 100000 0)"),
+               "100000");
+}
+
+#[test]
+fn mutual_tail_calls_run_in_constant_dump() {
+    assert_eq!(eval_shallow("
+(define (ev? n) (if (= n 0) #t (od? (- n 1))))
+(define (od? n) (if (= n 0) #f (ev? (- n 1))))
+(ev? 100001)"),
+               "#f");
+}
+
+#[test]
+fn derived_forms_keep_tail_position() {
+    assert_eq!(eval_shallow("
+(define (loop n)
+  (cond ((= n 0) 'done)
+        (else (let ((m (- n 1))) (and #t (or #f (begin (loop m))))))))
+(loop 100000)"),
+               "done");
+}
+
+#[test]
+fn non_tail_recursion_grows_the_dump() {
+    let mut interp = Interpreter::new().unwrap();
+    interp.set_limits(Limits { dump_depth: Some(64), ..Limits::default() });
+    assert!(interp.eval_str("(define (sum n) (if (= n 0) 0 (+ n (sum (- n 1))))) (sum 1000)").is_err());
+}
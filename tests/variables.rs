@@ -0,0 +1,64 @@
+extern crate secd;
+
+use secd::Interpreter;
+
+fn eval(input: &str) -> String {
+    let mut interp = Interpreter::new().unwrap();
+    match interp.eval_str(input) {
+        Ok(value) => value.to_string(),
+        Err(e) => panic!("{}: {}", input, e),
+    }
+}
+
+fn eval_err(input: &str) -> String {
+    let mut interp = Interpreter::new().unwrap();
+    match interp.eval_str(input) {
+        Ok(value) => panic!("{}: evaluated to {}", input, value),
+        Err(e) => e.to_string(),
+    }
+}
+
+#[test]
+fn set_updates_globals_and_locals() {
+    assert_eq!(eval("(define x 1) (set! x (+ x 1)) x"), "2");
+    assert_eq!(eval("(let ((x 1)) (set! x 10) (+ x 1))"), "11");
+}
+
+#[test]
+fn closures_share_mutated_bindings() {
+    assert_eq!(eval("
+(define (make-counter)
+  (let ((n 0))
+    (cons (lambda () (set! n (+ n 1)) n)
+          (lambda () n))))
+(define c (make-counter))
+((car c)) ((car c))
+(list ((cdr c)) ((car (make-counter))))"),
+               "(2 1)");
+}
+
+#[test]
+fn set_of_unbound_variable_is_an_error() {
+    assert!(eval_err("(set! nowhere 1)").contains("unbound variable: nowhere"));
+}
+
+#[test]
+fn closures_capture_their_own_frames() {
+    assert_eq!(eval("
+(define (make-adders l) (map (lambda (n) (lambda (x) (+ x n))) l))
+(map (lambda (f) (f 100)) (make-adders '(1 2 3)))"),
+               "(101 102 103)");
+}
+
+#[test]
+fn nested_frames_see_outer_updates() {
+    assert_eq!(eval("
+(define (outer)
+  (let ((a 1))
+    (let ((get (lambda () (let ((b 10)) (lambda () (+ a b))))))
+      (let ((f (get)))
+        (set! a 5)
+        (f)))))
+(outer)"),
+               "15");
+}
@@ -0,0 +1,49 @@
+extern crate secd;
+
+use secd::Interpreter;
+
+fn eval(input: &str) -> String {
+    let mut interp = Interpreter::new().unwrap();
+    match interp.eval_str(input) {
+        Ok(value) => value.to_string(),
+        Err(e) => panic!("{}: {}", input, e),
+    }
+}
+
+fn eval_err(input: &str) -> String {
+    let mut interp = Interpreter::new().unwrap();
+    match interp.eval_str(input) {
+        Ok(value) => panic!("{}: evaluated to {}", input, value),
+        Err(e) => e.to_string(),
+    }
+}
+
+#[test]
+fn vector_literals_are_self_evaluating() {
+    assert_eq!(eval("#(1 \"a\" (b c) #(2))"), "#(1 \"a\" (b c) #(2))");
+    assert_eq!(eval("(vector-ref #(1 2 3) 1)"), "2");
+    assert_eq!(eval("#u8(0 255)"), "#u8(0 255)");
+}
+
+#[test]
+fn vector_procedures() {
+    assert_eq!(eval("(define v (make-vector 3 0)) (vector-set! v 0 'x) (vector-fill! v 'y 1) v"), "#(x y y)");
+    assert_eq!(eval("(list (vector->list #(1 2 3)) (list->vector '(a b)) (vector-copy #(1 2 3) 1))"),
+               "((1 2 3) #(a b) #(2 3))");
+    assert!(eval_err("(vector-ref #(1 2) 2)").contains("vector-ref"));
+}
+
+#[test]
+fn bytevector_procedures() {
+    assert_eq!(eval("(define b (make-bytevector 2 7)) (bytevector-u8-set! b 1 255) b"), "#u8(7 255)");
+    assert_eq!(eval("(list (bytevector 1 2) (bytevector-length #u8(1 2 3)) (bytevector->list #u8(4)))"),
+               "(#u8(1 2) 3 (4))");
+    assert!(eval_err("(bytevector-u8-set! (make-bytevector 1 0) 0 256)").contains("bytevector-u8-set!"));
+    assert!(eval_err("#u8(256)").contains("256"));
+}
+
+#[test]
+fn vector_literals_are_immutable() {
+    assert!(eval_err("(vector-set! #(1 2) 0 'x)").contains("vector-set!"));
+    assert!(eval_err("(bytevector-u8-set! #u8(1) 0 2)").contains("bytevector-u8-set!"));
+}